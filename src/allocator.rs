@@ -1,4 +1,17 @@
-use linked_list_allocator::LockedHeap;
+//! The kernel heap, a linked list allocator over `HEAP_SIZE` bytes at
+//! `HEAP_START`.
+//!
+//! Freed blocks are merged with their free neighbours as they go back to the
+//! list, so the free list never holds two adjacent blocks and there is nothing
+//! left to compact : fragmentation comes from live allocations sitting between
+//! free blocks, which can't be moved. `stats` measures it.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use linked_list_allocator::{Heap, LockedHeap};
+use x86_64::instructions::interrupts;
+
+use crate::{cover, kbug};
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
@@ -23,10 +36,12 @@ pub fn init_heap(
     };
 
     for page in page_range {
+        cover!("init_heap_page");
         let frame = frame_allocator
             .allocate_frame()
             .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let flags =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
         unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
     }
 
@@ -40,7 +55,133 @@ pub fn init_heap(
 #[global_allocator]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
+/// Size classes of `HeapStats::histogram` : class `i` counts the free blocks
+/// of at least `16 << i` bytes, the last one also everything bigger.
+pub const HISTOGRAM_CLASSES: usize = 8;
+const SMALLEST_CLASS: usize = 16;
+/// Smallest block the allocator hands out or keeps as a hole.
+const MIN_BLOCK: usize = 2 * core::mem::size_of::<usize>();
+/// Free blocks `stats` measures, the ones past that are only in `free`.
+const MAX_MEASURED_BLOCKS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+    /// Biggest allocation that can currently succeed, in bytes.
+    pub largest_free_block: usize,
+    /// Free blocks measured, at most `MAX_MEASURED_BLOCKS`.
+    pub free_blocks: usize,
+    /// Free blocks per size class, see `HISTOGRAM_CLASSES`.
+    pub histogram: [usize; HISTOGRAM_CLASSES],
+}
+
+fn try_allocate(heap: &mut Heap, size: usize) -> Option<(NonNull<u8>, Layout)> {
+    let layout = Layout::from_size_align(size, 1).ok()?;
+    heap.allocate_first_fit(layout).ok().map(|ptr| (ptr, layout))
+}
+
+fn fits(heap: &mut Heap, size: usize) -> bool {
+    match try_allocate(heap, size) {
+        Some((ptr, layout)) => {
+            unsafe { heap.deallocate(ptr, layout) };
+            true
+        }
+        None => false,
+    }
+}
+
+/// Size of the biggest block of `heap` that can be allocated, 0 if none.
+///
+/// Sizes are rounded up to 8 bytes and a hole can't be split if the remainder
+/// would be under `MIN_BLOCK` : a hole of `size` bytes takes exactly `size`
+/// or up to `size - MIN_BLOCK`, but not `size - 8`. Whether a size fits isn't
+/// monotone then, except over multiples of `MIN_BLOCK` where it is again. So
+/// this searches those, and the hole found is at most 24 bytes bigger.
+fn largest_fit(heap: &mut Heap) -> usize {
+    let (mut fit, mut too_big) = (0, heap.free() / MIN_BLOCK + 1);
+    while too_big - fit > 1 {
+        let blocks = fit + (too_big - fit) / 2;
+        if fits(heap, blocks * MIN_BLOCK) {
+            fit = blocks;
+        } else {
+            too_big = blocks;
+        }
+    }
+    let fit = fit * MIN_BLOCK;
+    [24, 16, 8]
+        .iter()
+        .map(|step| fit + step)
+        .find(|&size| size >= MIN_BLOCK && fits(heap, size))
+        .unwrap_or(fit)
+}
+
+fn size_class(size: usize) -> usize {
+    let mut class = 0;
+    while class + 1 < HISTOGRAM_CLASSES && size >= SMALLEST_CLASS << (class + 1) {
+        class += 1;
+    }
+    class
+}
+
+/// Measures the heap fragmentation.
+///
+/// The allocator doesn't expose its free list, so this takes the largest free
+/// block over and over to find their sizes, then gives them all back. The heap
+/// stays locked (with interrupts off) meanwhile, and ends up as it was. That's
+/// about 20 allocations per free block, for up to `MAX_MEASURED_BLOCKS` blocks.
+pub fn stats() -> HeapStats {
+    interrupts::without_interrupts(|| {
+        let mut heap = ALLOCATOR.lock();
+        let mut stats = HeapStats {
+            size: heap.size(),
+            used: heap.used(),
+            free: heap.free(),
+            largest_free_block: 0,
+            free_blocks: 0,
+            histogram: [0; HISTOGRAM_CLASSES],
+        };
+
+        let mut taken = [None; MAX_MEASURED_BLOCKS];
+        for block in taken.iter_mut() {
+            let size = largest_fit(&mut heap);
+            if size == 0 {
+                break;
+            }
+            *block = try_allocate(&mut heap, size);
+            stats.largest_free_block = stats.largest_free_block.max(size);
+            stats.free_blocks += 1;
+            stats.histogram[size_class(size)] += 1;
+        }
+        for (ptr, layout) in taken.iter().rev().flatten() {
+            unsafe { heap.deallocate(*ptr, *layout) };
+        }
+        stats
+    })
+}
+
+#[test_case]
+fn test_largest_fit_finds_the_whole_hole() {
+    let mut area = [0u64; 64];
+    let mut heap = Heap::empty();
+    unsafe { heap.init(area.as_mut_ptr() as usize, 512) };
+
+    // a 200 byte hole between live blocks, the only one left
+    let hole = try_allocate(&mut heap, 200).unwrap();
+    let _fence = try_allocate(&mut heap, 16).unwrap();
+    let _rest = try_allocate(&mut heap, 512 - 216).unwrap();
+    unsafe { heap.deallocate(hole.0, hole.1) };
+
+    // 192 would leave 8 bytes, under the smallest hole
+    assert!(!fits(&mut heap, 192));
+    assert!(fits(&mut heap, 184));
+    assert_eq!(largest_fit(&mut heap), 200);
+    assert_eq!(heap.free(), 200);
+}
+
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
-    panic!("allocation error: {:?}", layout)
+    cover!("alloc_error");
+    kbug!("allocation error: {:?}", layout)
 }
@@ -0,0 +1,85 @@
+//! Assertion and bug macros that log where and in which context things went
+//! wrong before (optionally) panicking.
+//!
+//! * `kassert!(cond)` / `kassert!(cond, "fmt", args)` : panics if `cond` is false
+//! * `kbug!("fmt", args)` : unconditional, never returns
+//! * `warn_once!("fmt", args)` : logs a warning the first time the call site is reached,
+//!   and evaluates to whether it did
+
+use core::fmt;
+
+use crate::{cpu, error, interrupts, warn};
+
+#[doc(hidden)]
+pub fn report(kind: &str, file: &str, line: u32, message: fmt::Arguments) {
+    error!("{}: {}", kind, message);
+    error!(
+        "  at {}:{} (cpu {}, interrupt depth {})",
+        file,
+        line,
+        cpu::id(),
+        interrupts::nesting()
+    );
+}
+
+#[doc(hidden)]
+pub fn bug(kind: &str, file: &str, line: u32, message: fmt::Arguments) -> ! {
+    report(kind, file, line, message);
+    panic!("{} at {}:{}: {}", kind, file, line, message);
+}
+
+#[doc(hidden)]
+pub fn warn(file: &str, line: u32, message: fmt::Arguments) {
+    warn!("{}", message);
+    warn!(
+        "  at {}:{} (cpu {}, interrupt depth {}), further warnings from here are muted",
+        file,
+        line,
+        cpu::id(),
+        interrupts::nesting()
+    );
+}
+
+#[macro_export]
+macro_rules! kassert {
+    ($cond:expr) => (
+        $crate::kassert!($cond, "{}", stringify!($cond))
+    );
+    ($cond:expr, $($arg:tt)+) => (
+        if !$cond {
+            $crate::bug::bug("ASSERTION FAILED", file!(), line!(), format_args!($($arg)+));
+        }
+    );
+}
+
+#[macro_export]
+macro_rules! kbug {
+    ($($arg:tt)+) => (
+        $crate::bug::bug("KERNEL BUG", file!(), line!(), format_args!($($arg)+))
+    );
+}
+
+#[macro_export]
+macro_rules! warn_once {
+    ($($arg:tt)+) => ({
+        static WARNED: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+        let first = !WARNED.swap(true, core::sync::atomic::Ordering::Relaxed);
+        if first {
+            $crate::bug::warn(file!(), line!(), format_args!($($arg)+));
+        }
+        first
+    });
+}
+
+#[test_case]
+fn test_warn_once_per_call_site() {
+    fn site() -> bool {
+        warn_once!("warn_once test, first site")
+    }
+
+    assert!(site());
+    assert!(!site());
+    assert!(!site());
+    // a different call site has its own flag
+    assert!(warn_once!("warn_once test, second site"));
+}
@@ -0,0 +1,181 @@
+//! The `$` color escapes of the console.
+//!
+//! Log and print format strings carry their colors inline :
+//!
+//! * `$XY` switches to background `X` and foreground `Y`, two hex digits
+//!   (`$0C` is light red on black)
+//! * `$!` goes back to the default color
+//! * `$$` is a literal `$`
+//!
+//! The VGA writer renders them, sinks without colors (serial) write the text
+//! through `Strip`. A `$` followed by anything else is malformed : it is dropped
+//! at runtime, and rejected at build time in the format strings of `print!`,
+//! `println!` and the log macros by `check_colors!`.
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token<'a> {
+    /// Text to write as is, `$$` comes out as a `"$"` of its own.
+    Text(&'a str),
+    /// VGA attribute byte, background in the high nibble.
+    Color(u8),
+    Reset,
+}
+
+const fn hex_digit(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+const fn is_hex(byte: u8) -> bool {
+    matches!(byte, b'0'..=b'9' | b'a'..=b'f' | b'A'..=b'F')
+}
+
+/// Length of the escape starting at the `$` at `bytes[at]`, 0 if malformed.
+const fn escape_len(bytes: &[u8], at: usize) -> usize {
+    if at + 1 >= bytes.len() {
+        return 0;
+    }
+    match bytes[at + 1] {
+        b'$' | b'!' => 2,
+        high => {
+            if at + 2 < bytes.len() && is_hex(high) && is_hex(bytes[at + 2]) {
+                3
+            } else {
+                0
+            }
+        }
+    }
+}
+
+/// Byte offset of the first malformed escape in `s`, if any.
+pub const fn first_malformed(s: &str) -> Option<usize> {
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'$' {
+            let len = escape_len(bytes, i);
+            if len == 0 {
+                return Some(i);
+            }
+            i += len;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+pub const fn is_valid(s: &str) -> bool {
+    matches!(first_malformed(s), None)
+}
+
+/// Fails the build if the string literal `$fmt` has a malformed color escape.
+///
+/// Stable const evaluation can't panic with a message yet, so the error shows
+/// up as an index out of bounds in this macro.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! check_colors {
+    ($fmt:expr) => {
+        const _: () = [()][!$crate::color::is_valid($fmt) as usize];
+    };
+}
+
+pub struct Tokens<'a> {
+    rest: &'a str,
+}
+
+/// Splits `s` into text and color changes.
+pub fn tokens(s: &str) -> Tokens<'_> {
+    Tokens { rest: s }
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        loop {
+            let bytes = self.rest.as_bytes();
+            if bytes.is_empty() {
+                return None;
+            }
+            if bytes[0] != b'$' {
+                let end = self.rest.find('$').unwrap_or(self.rest.len());
+                let (text, rest) = self.rest.split_at(end);
+                self.rest = rest;
+                return Some(Token::Text(text));
+            }
+
+            let len = escape_len(bytes, 0);
+            let token = match len {
+                2 if bytes[1] == b'!' => Token::Reset,
+                2 => Token::Text(&self.rest[1..2]),
+                3 => match (hex_digit(bytes[1]), hex_digit(bytes[2])) {
+                    (Some(high), Some(low)) => Token::Color(high << 4 | low),
+                    _ => unreachable!(),
+                },
+                // malformed, drop the `$`
+                _ => {
+                    self.rest = &self.rest[1..];
+                    continue;
+                }
+            };
+            self.rest = &self.rest[len..];
+            return Some(token);
+        }
+    }
+}
+
+/// The text of `s` without its color escapes.
+pub fn strip(s: &str) -> impl Iterator<Item = &str> {
+    tokens(s).filter_map(|token| match token {
+        Token::Text(text) => Some(text),
+        _ => None,
+    })
+}
+
+/// Writes to the inner writer with the color escapes removed.
+pub struct Strip<W: fmt::Write>(pub W);
+
+impl<W: fmt::Write> fmt::Write for Strip<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for text in strip(s) {
+            self.0.write_str(text)?;
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_color_tokens() {
+    let mut parsed = tokens("a$0Cb$!$$c");
+    assert_eq!(parsed.next(), Some(Token::Text("a")));
+    assert_eq!(parsed.next(), Some(Token::Color(0x0C)));
+    assert_eq!(parsed.next(), Some(Token::Text("b")));
+    assert_eq!(parsed.next(), Some(Token::Reset));
+    assert_eq!(parsed.next(), Some(Token::Text("$")));
+    assert_eq!(parsed.next(), Some(Token::Text("c")));
+    assert_eq!(parsed.next(), None);
+}
+
+#[test_case]
+fn test_malformed_colors() {
+    let text = "$0agreen $$ $ok $!reset $o";
+    assert_eq!(first_malformed(text), Some(12));
+    assert_eq!(first_malformed("trailing $"), Some(9));
+    assert!(is_valid("[$04ERRO$!] $$5"));
+
+    let mut stripped = [0u8; 32];
+    let mut len = 0;
+    for text in strip(text) {
+        stripped[len..len + text.len()].copy_from_slice(text.as_bytes());
+        len += text.len();
+    }
+    assert_eq!(&stripped[..len], b"green $ ok reset o");
+}
@@ -0,0 +1,86 @@
+//! Manual coverage counters, built with the `coverage` feature.
+//!
+//! `cover!("name")` marks a point of interest : each one owns a static counter
+//! that joins a global list the first time it is hit. `dump` prints the list
+//! over serial, which the test runner does after the last test so the host can
+//! track which fault handler and allocator paths a run went through. Counters
+//! that were never hit are not listed.
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+use crate::qemu_println;
+
+pub struct Counter {
+    name: &'static str,
+    hits: AtomicUsize,
+    registered: AtomicBool,
+    next: AtomicPtr<Counter>,
+}
+
+static HEAD: AtomicPtr<Counter> = AtomicPtr::new(ptr::null_mut());
+
+impl Counter {
+    pub const fn new(name: &'static str) -> Counter {
+        Counter {
+            name,
+            hits: AtomicUsize::new(0),
+            registered: AtomicBool::new(false),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn hit(&'static self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        if !self.registered.swap(true, Ordering::AcqRel) {
+            // lock free push, counters can be hit from interrupt handlers
+            let this = self as *const Counter as *mut Counter;
+            let mut head = HEAD.load(Ordering::Acquire);
+            loop {
+                self.next.store(head, Ordering::Relaxed);
+                match HEAD.compare_exchange_weak(head, this, Ordering::AcqRel, Ordering::Acquire)
+                {
+                    Ok(_) => break,
+                    Err(current) => head = current,
+                }
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+/// Calls `f` with every counter hit so far, most recently registered first.
+pub fn for_each(mut f: impl FnMut(&'static Counter)) {
+    let mut current = HEAD.load(Ordering::Acquire);
+    while let Some(counter) = unsafe { (current as *const Counter).as_ref() } {
+        f(counter);
+        current = counter.next.load(Ordering::Acquire);
+    }
+}
+
+/// Prints every hit counter over serial, one `COVERAGE <name> <hits>` line each.
+pub fn dump() {
+    qemu_println!("COVERAGE BEGIN");
+    for_each(|counter| qemu_println!("COVERAGE {} {}", counter.name(), counter.hits()));
+    qemu_println!("COVERAGE END");
+}
+
+/// Counts a pass through this point when built with the `coverage` feature.
+#[macro_export]
+macro_rules! cover {
+    ($name:expr) => {
+        #[cfg(feature = "coverage")]
+        {
+            static COUNTER: $crate::coverage::Counter =
+                $crate::coverage::Counter::new(concat!(module_path!(), "::", $name));
+            COUNTER.hit();
+        }
+    };
+}
@@ -0,0 +1,7 @@
+//! Information about the CPU we are running on.
+
+/// Initial local APIC id of the current CPU, from `cpuid` leaf 1.
+pub fn id() -> u8 {
+    let leaf = unsafe { core::arch::x86_64::__cpuid(1) };
+    (leaf.ebx >> 24) as u8
+}
@@ -0,0 +1,301 @@
+//! Small, allocation free crypto primitives used across the kernel.
+//!
+//! * `crc32` : the IEEE 802.3 polynomial (FAT, ethernet and gzip all use this one)
+//! * `Sha256` / `sha256` : FIPS 180-4
+//! * `hmac_sha256` : RFC 2104 keyed with SHA-256
+//!
+//! None of these are constant time, they are meant for integrity checks and
+//! seeding, not for protecting secrets against a local attacker.
+
+const CRC32_POLY: u32 = 0xEDB8_8320; // reversed 0x04C11DB7
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continues a CRC32 computation.
+///
+/// `crc` is the value returned by a previous call (or `0` to start), which
+/// allows checksumming data that arrives in several chunks.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Returns the CRC32 of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+pub const SHA256_BLOCK_SIZE: usize = 64;
+pub const SHA256_DIGEST_SIZE: usize = 32;
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const SHA256_INIT: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Incremental SHA-256 hasher.
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    buffer: [u8; SHA256_BLOCK_SIZE],
+    buffer_len: usize,
+    total_len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Sha256 {
+        Sha256::new()
+    }
+}
+
+impl Sha256 {
+    pub fn new() -> Sha256 {
+        Sha256 {
+            state: SHA256_INIT,
+            buffer: [0; SHA256_BLOCK_SIZE],
+            buffer_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len = self.total_len.wrapping_add(data.len() as u64);
+
+        // fill up a partially filled block first
+        if self.buffer_len > 0 {
+            let take = core::cmp::min(SHA256_BLOCK_SIZE - self.buffer_len, data.len());
+            self.buffer[self.buffer_len..self.buffer_len + take].copy_from_slice(&data[..take]);
+            self.buffer_len += take;
+            data = &data[take..];
+            if self.buffer_len < SHA256_BLOCK_SIZE {
+                return;
+            }
+            let block = self.buffer;
+            self.compress(&block);
+            self.buffer_len = 0;
+        }
+
+        while data.len() >= SHA256_BLOCK_SIZE {
+            let mut block = [0u8; SHA256_BLOCK_SIZE];
+            block.copy_from_slice(&data[..SHA256_BLOCK_SIZE]);
+            self.compress(&block);
+            data = &data[SHA256_BLOCK_SIZE..];
+        }
+
+        self.buffer[..data.len()].copy_from_slice(data);
+        self.buffer_len = data.len();
+    }
+
+    pub fn finalize(mut self) -> [u8; SHA256_DIGEST_SIZE] {
+        let bit_len = self.total_len.wrapping_mul(8);
+
+        // padding : a single 1 bit, zeros, then the length on the last 8 bytes
+        let mut padding = [0u8; SHA256_BLOCK_SIZE * 2];
+        padding[0] = 0x80;
+        let pad_len = if self.buffer_len < 56 {
+            56 - self.buffer_len
+        } else {
+            120 - self.buffer_len
+        };
+        padding[pad_len..pad_len + 8].copy_from_slice(&bit_len.to_be_bytes());
+        let total_len = self.total_len;
+        self.update(&padding[..pad_len + 8]);
+        self.total_len = total_len;
+
+        let mut digest = [0u8; SHA256_DIGEST_SIZE];
+        for (i, word) in self.state.iter().enumerate() {
+            digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; SHA256_BLOCK_SIZE]) {
+        let mut w = [0u32; 64];
+        for (word, bytes) in w.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for (k, word) in SHA256_K.iter().zip(w.iter()) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(*k)
+                .wrapping_add(*word);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+}
+
+/// Returns the SHA-256 digest of `data`.
+pub fn sha256(data: &[u8]) -> [u8; SHA256_DIGEST_SIZE] {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Returns the HMAC-SHA-256 of `message` keyed with `key`.
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; SHA256_DIGEST_SIZE] {
+    // keys longer than a block are hashed first, shorter ones are zero padded
+    let mut block_key = [0u8; SHA256_BLOCK_SIZE];
+    if key.len() > SHA256_BLOCK_SIZE {
+        block_key[..SHA256_DIGEST_SIZE].copy_from_slice(&sha256(key));
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner_pad = [0x36u8; SHA256_BLOCK_SIZE];
+    let mut outer_pad = [0x5cu8; SHA256_BLOCK_SIZE];
+    for ((inner, outer), key) in inner_pad
+        .iter_mut()
+        .zip(outer_pad.iter_mut())
+        .zip(block_key.iter())
+    {
+        *inner ^= key;
+        *outer ^= key;
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(&inner_pad);
+    inner.update(message);
+    let inner_digest = inner.finalize();
+
+    let mut outer = Sha256::new();
+    outer.update(&outer_pad);
+    outer.update(&inner_digest);
+    outer.finalize()
+}
+
+/// Formats a digest as lowercase hexadecimal, the way `sha256sum` prints it.
+pub struct HexDigest<'a>(pub &'a [u8]);
+
+impl core::fmt::Display for HexDigest<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Renders `digest` through `HexDigest`, so the tests check the same output
+/// `sha256sum` users see.
+#[cfg(test)]
+fn hex(digest: &[u8]) -> crate::testing::FmtBuffer {
+    use core::fmt::Write;
+
+    let mut buffer = crate::testing::FmtBuffer::new();
+    write!(buffer, "{}", HexDigest(digest)).unwrap();
+    buffer
+}
+
+#[test_case]
+fn test_crc32_check_value() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32_update(crc32(b"12345"), b"6789"), 0xCBF4_3926);
+}
+
+#[test_case]
+fn test_sha256_vectors() {
+    assert_eq!(
+        hex(&sha256(b"")).as_bytes(),
+        &b"e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"[..]
+    );
+    assert_eq!(
+        hex(&sha256(b"abc")).as_bytes(),
+        &b"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"[..]
+    );
+    assert_eq!(
+        hex(&sha256(
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+        ))
+        .as_bytes(),
+        &b"248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"[..]
+    );
+}
+
+#[test_case]
+fn test_sha256_incremental() {
+    let data = [b'a'; 1000];
+    let mut hasher = Sha256::new();
+    for chunk in data.chunks(7) {
+        hasher.update(chunk);
+    }
+    assert_eq!(hasher.finalize(), sha256(&data));
+}
+
+#[test_case]
+fn test_hmac_sha256_rfc4231() {
+    // test case 2
+    assert_eq!(
+        hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")).as_bytes(),
+        &b"5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"[..]
+    );
+    // test case 6 : key larger than the block size
+    let key = [0xaa; 131];
+    assert_eq!(
+        hex(&hmac_sha256(
+            &key,
+            b"Test Using Larger Than Block-Size Key - Hash Key First"
+        ))
+        .as_bytes(),
+        &b"60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"[..]
+    );
+}
@@ -0,0 +1,162 @@
+//! Driver power management.
+//!
+//! Drivers of devices that buffer data or run on their own register a `Driver`
+//! here, so that they are told before the machine is suspended, reset or
+//! powered off instead of being abandoned mid-transfer. Devices are suspended
+//! and shut down in the reverse order they were registered in, and resumed in
+//! registration order, so a device goes down before what it depends on.
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{debug, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriverError {
+    /// The device didn't answer in time.
+    Timeout,
+    /// The device refused or failed the transition.
+    Failed,
+}
+
+pub trait Driver: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Stops the device, keeping what `resume` needs to restart it.
+    fn suspend(&self) -> Result<(), DriverError> {
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<(), DriverError> {
+        Ok(())
+    }
+
+    /// Quiesces the device before a reset or poweroff : flush what is
+    /// buffered, stop any activity. There is no way back from it.
+    fn shutdown(&self) {}
+}
+
+pub const MAX_DRIVERS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegistryFull;
+
+/// A driver that failed to suspend. The drivers suspended before it were
+/// resumed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuspendError {
+    pub driver: &'static str,
+    pub error: DriverError,
+}
+
+type Drivers = [Option<&'static dyn Driver>; MAX_DRIVERS];
+
+static DRIVERS: Mutex<Drivers> = Mutex::new([None; MAX_DRIVERS]);
+
+pub fn register(driver: &'static dyn Driver) -> Result<(), RegistryFull> {
+    interrupts::without_interrupts(|| {
+        let mut drivers = DRIVERS.lock();
+        let slot = drivers
+            .iter()
+            .position(Option::is_none)
+            .ok_or(RegistryFull)?;
+        debug!("Registered driver {}", driver.name());
+        drivers[slot] = Some(driver);
+        Ok(())
+    })
+}
+
+/// The registered drivers, hooks run without the registry locked.
+fn drivers() -> Drivers {
+    interrupts::without_interrupts(|| *DRIVERS.lock())
+}
+
+pub fn suspend_all() -> Result<(), SuspendError> {
+    let drivers = drivers();
+    let registered = drivers.iter().flatten();
+    let count = registered.clone().count();
+    for (suspended, driver) in registered.clone().rev().enumerate() {
+        if let Err(error) = driver.suspend() {
+            warn!("Driver {} failed to suspend: {:?}", driver.name(), error);
+            // bring back the ones already down, in the resume order
+            for driver in registered.skip(count - suspended) {
+                resume(*driver);
+            }
+            return Err(SuspendError {
+                driver: driver.name(),
+                error,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn resume(driver: &dyn Driver) {
+    if let Err(error) = driver.resume() {
+        warn!("Driver {} failed to resume: {:?}", driver.name(), error);
+    }
+}
+
+/// Resumes every driver. Failures are logged, the device stays unusable.
+pub fn resume_all() {
+    for driver in drivers().iter().flatten() {
+        resume(*driver);
+    }
+}
+
+pub fn shutdown_all() {
+    for driver in drivers().iter().flatten().rev() {
+        debug!("Shutting down {}", driver.name());
+        driver.shutdown();
+    }
+}
+
+#[test_case]
+fn test_suspend_resume_cycle() {
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    static SUSPENDS: AtomicUsize = AtomicUsize::new(0);
+    static RESUMES: AtomicUsize = AtomicUsize::new(0);
+    static REFUSE: AtomicBool = AtomicBool::new(false);
+
+    struct TestDriver;
+
+    impl Driver for TestDriver {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn suspend(&self) -> Result<(), DriverError> {
+            if REFUSE.load(Ordering::Relaxed) {
+                return Err(DriverError::Failed);
+            }
+            SUSPENDS.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+
+        fn resume(&self) -> Result<(), DriverError> {
+            RESUMES.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    static DRIVER: TestDriver = TestDriver;
+    register(&DRIVER).expect("driver registry full");
+
+    assert_eq!(suspend_all(), Ok(()));
+    resume_all();
+    assert_eq!(SUSPENDS.load(Ordering::Relaxed), 1);
+    assert_eq!(RESUMES.load(Ordering::Relaxed), 1);
+
+    // registered last, so suspended first : nothing to roll back
+    REFUSE.store(true, Ordering::Relaxed);
+    assert_eq!(
+        suspend_all(),
+        Err(SuspendError {
+            driver: "test",
+            error: DriverError::Failed
+        })
+    );
+    REFUSE.store(false, Ordering::Relaxed);
+    assert_eq!(RESUMES.load(Ordering::Relaxed), 1);
+}
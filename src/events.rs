@@ -0,0 +1,88 @@
+//! Kernel event bus.
+//!
+//! Drivers publish what happened to their hardware, and policy code (logging,
+//! notifications, mounting...) subscribes to it, so neither needs to know about
+//! the other.
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Device {
+    Ps2Keyboard,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    DeviceAttached(Device),
+    DeviceDetached(Device),
+}
+
+pub type Listener = fn(&Event);
+
+pub const MAX_LISTENERS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subscription(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusFull;
+
+static LISTENERS: Mutex<[Option<Listener>; MAX_LISTENERS]> = Mutex::new([None; MAX_LISTENERS]);
+
+pub fn subscribe(listener: Listener) -> Result<Subscription, BusFull> {
+    interrupts::without_interrupts(|| {
+        let mut listeners = LISTENERS.lock();
+        let slot = listeners
+            .iter()
+            .position(Option::is_none)
+            .ok_or(BusFull)?;
+        listeners[slot] = Some(listener);
+        Ok(Subscription(slot))
+    })
+}
+
+pub fn unsubscribe(subscription: Subscription) {
+    interrupts::without_interrupts(|| {
+        LISTENERS.lock()[subscription.0] = None;
+    });
+}
+
+/// Calls every listener with `event`, in subscription order.
+///
+/// Listeners run without the bus locked, so they may publish or subscribe
+/// themselves. May be called from interrupt handlers.
+pub fn publish(event: Event) {
+    let listeners = interrupts::without_interrupts(|| *LISTENERS.lock());
+    for listener in listeners.iter().flatten() {
+        listener(&event);
+    }
+}
+
+/// Default listener logging device changes.
+pub fn log_event(event: &Event) {
+    match event {
+        Event::DeviceAttached(device) => info!("Device attached: {:?}", device),
+        Event::DeviceDetached(device) => warn!("Device detached: {:?}", device),
+    }
+}
+
+#[test_case]
+fn test_publish_reaches_subscribers() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+    fn count(event: &Event) {
+        if *event == Event::DeviceDetached(Device::Ps2Keyboard) {
+            RECEIVED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    let subscription = subscribe(count).expect("event bus full");
+    publish(Event::DeviceDetached(Device::Ps2Keyboard));
+    unsubscribe(subscription);
+    publish(Event::DeviceDetached(Device::Ps2Keyboard));
+    assert_eq!(RECEIVED.load(Ordering::Relaxed), 1);
+}
@@ -0,0 +1,376 @@
+//! Decompressor for raw deflate streams (RFC 1951) and gzip members (RFC 1952).
+//!
+//! The decoder is a straightforward canonical Huffman implementation, close to
+//! zlib's `puff.c` : slow but small, and it only needs the heap for the output.
+
+use alloc::vec::Vec;
+
+use crate::crypto;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InflateError {
+    /// The input ended in the middle of a block.
+    UnexpectedEof,
+    /// Block type 3 is reserved.
+    InvalidBlockType,
+    /// A stored block's LEN and NLEN fields don't match.
+    StoredLengthMismatch,
+    /// A Huffman table is over-subscribed or a code isn't in the table.
+    InvalidCode,
+    /// A back reference points before the start of the output.
+    InvalidDistance,
+    /// The gzip header is missing, truncated, or uses an unknown method.
+    InvalidHeader,
+    /// The CRC32 in the gzip trailer doesn't match the decompressed data.
+    ChecksumMismatch,
+    /// The size in the gzip trailer doesn't match the decompressed data.
+    SizeMismatch,
+}
+
+const MAX_BITS: usize = 15;
+const MAX_LIT_CODES: usize = 288;
+const MAX_DIST_CODES: usize = 30;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which the code length code lengths are stored in a dynamic block.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buf: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            pos: 0,
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Reads `count` bits (at most 16), least significant bit first.
+    fn bits(&mut self, count: u32) -> Result<u32, InflateError> {
+        while self.bit_count < count {
+            let byte = *self.data.get(self.pos).ok_or(InflateError::UnexpectedEof)?;
+            self.pos += 1;
+            self.bit_buf |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buf & ((1 << count) - 1);
+        self.bit_buf >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    /// Drops the remaining bits of the current byte.
+    fn align_to_byte(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+
+    /// Number of input bytes consumed so far.
+    fn consumed(&self) -> usize {
+        self.pos
+    }
+}
+
+/// A canonical Huffman table : how many codes have each length, and the
+/// symbols ordered by code.
+struct Huffman {
+    count: [u16; MAX_BITS + 1],
+    symbol: [u16; MAX_LIT_CODES],
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, InflateError> {
+        let mut huffman = Huffman {
+            count: [0; MAX_BITS + 1],
+            symbol: [0; MAX_LIT_CODES],
+        };
+        for &length in lengths {
+            huffman.count[length as usize] += 1;
+        }
+
+        // reject over-subscribed tables, incomplete ones are allowed
+        let mut left: i32 = 1;
+        for len in 1..=MAX_BITS {
+            left <<= 1;
+            left -= huffman.count[len] as i32;
+            if left < 0 {
+                return Err(InflateError::InvalidCode);
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offsets[len + 1] = offsets[len] + huffman.count[len];
+        }
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                huffman.symbol[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(huffman)
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, InflateError> {
+        let mut code: i32 = 0; // bits read so far
+        let mut first: i32 = 0; // first code of the current length
+        let mut index: i32 = 0; // index of the first code of the current length in `symbol`
+        for len in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.count[len] as i32;
+            if code - count < first {
+                return Ok(self.symbol[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(InflateError::InvalidCode)
+    }
+}
+
+fn fixed_tables() -> Result<(Huffman, Huffman), InflateError> {
+    let mut lengths = [0u8; MAX_LIT_CODES];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    let literals = Huffman::new(&lengths)?;
+    let distances = Huffman::new(&[5; MAX_DIST_CODES])?;
+    Ok((literals, distances))
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), InflateError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > MAX_DIST_CODES {
+        return Err(InflateError::InvalidCode);
+    }
+
+    let mut lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&lengths)?;
+
+    // literal/length and distance code lengths share one run-length encoding
+    let mut lengths = [0u8; MAX_LIT_CODES + MAX_DIST_CODES];
+    let total = literal_count + distance_count;
+    let mut index = 0;
+    while index < total {
+        let symbol = code_lengths.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if index == 0 {
+                    return Err(InflateError::InvalidCode);
+                }
+                (lengths[index - 1], 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if index + repeat > total {
+            return Err(InflateError::InvalidCode);
+        }
+        for length in &mut lengths[index..index + repeat] {
+            *length = value;
+        }
+        index += repeat;
+    }
+
+    // a block without an end of block code could never terminate
+    if lengths[256] == 0 {
+        return Err(InflateError::InvalidCode);
+    }
+
+    let literals = Huffman::new(&lengths[..literal_count])?;
+    let distances = Huffman::new(&lengths[literal_count..total])?;
+    Ok((literals, distances))
+}
+
+fn inflate_codes(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), InflateError> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => output.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let symbol = symbol - 257;
+                if symbol >= LENGTH_BASE.len() {
+                    return Err(InflateError::InvalidCode);
+                }
+                let length = LENGTH_BASE[symbol] as usize
+                    + reader.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+                let symbol = distances.decode(reader)? as usize;
+                if symbol >= DIST_BASE.len() {
+                    return Err(InflateError::InvalidCode);
+                }
+                let distance = DIST_BASE[symbol] as usize
+                    + reader.bits(DIST_EXTRA[symbol] as u32)? as usize;
+                if distance > output.len() {
+                    return Err(InflateError::InvalidDistance);
+                }
+
+                // byte by byte : the source and destination may overlap
+                output.reserve(length);
+                let start = output.len() - distance;
+                for i in 0..length {
+                    let byte = output[start + i];
+                    output.push(byte);
+                }
+            }
+        }
+    }
+}
+
+fn inflate_stored(reader: &mut BitReader, output: &mut Vec<u8>) -> Result<(), InflateError> {
+    reader.align_to_byte();
+    let header = reader
+        .data
+        .get(reader.pos..reader.pos + 4)
+        .ok_or(InflateError::UnexpectedEof)?;
+    let length = u16::from_le_bytes([header[0], header[1]]);
+    let inverted_length = u16::from_le_bytes([header[2], header[3]]);
+    if length != !inverted_length {
+        return Err(InflateError::StoredLengthMismatch);
+    }
+    reader.pos += 4;
+
+    let data = reader
+        .data
+        .get(reader.pos..reader.pos + length as usize)
+        .ok_or(InflateError::UnexpectedEof)?;
+    output.extend_from_slice(data);
+    reader.pos += length as usize;
+    Ok(())
+}
+
+/// Decompresses the raw deflate stream at the start of `data` into `output`,
+/// returning how many input bytes the stream took.
+pub fn inflate_into(data: &[u8], output: &mut Vec<u8>) -> Result<usize, InflateError> {
+    let mut reader = BitReader::new(data);
+    loop {
+        let is_last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => inflate_stored(&mut reader, output)?,
+            1 => {
+                let (literals, distances) = fixed_tables()?;
+                inflate_codes(&mut reader, output, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_tables(&mut reader)?;
+                inflate_codes(&mut reader, output, &literals, &distances)?;
+            }
+            _ => return Err(InflateError::InvalidBlockType),
+        }
+        if is_last {
+            return Ok(reader.consumed());
+        }
+    }
+}
+
+/// Decompresses a raw deflate stream.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, InflateError> {
+    let mut output = Vec::new();
+    inflate_into(data, &mut output)?;
+    Ok(output)
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const GZIP_METHOD_DEFLATE: u8 = 8;
+const GZIP_FLAG_HCRC: u8 = 1 << 1;
+const GZIP_FLAG_EXTRA: u8 = 1 << 2;
+const GZIP_FLAG_NAME: u8 = 1 << 3;
+const GZIP_FLAG_COMMENT: u8 = 1 << 4;
+
+/// Returns true if `data` starts like a gzip member.
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.len() >= 3 && data[..2] == GZIP_MAGIC && data[2] == GZIP_METHOD_DEFLATE
+}
+
+/// Returns the offset of the deflate stream in a gzip member.
+fn gzip_header_len(data: &[u8]) -> Result<usize, InflateError> {
+    if data.len() < 10 || !is_gzip(data) {
+        return Err(InflateError::InvalidHeader);
+    }
+    let flags = data[3];
+    let mut pos = 10;
+
+    if flags & GZIP_FLAG_EXTRA != 0 {
+        let extra = data.get(pos..pos + 2).ok_or(InflateError::InvalidHeader)?;
+        pos += 2 + u16::from_le_bytes([extra[0], extra[1]]) as usize;
+    }
+    for &flag in &[GZIP_FLAG_NAME, GZIP_FLAG_COMMENT] {
+        if flags & flag != 0 {
+            // zero terminated latin-1 string
+            let rest = data.get(pos..).ok_or(InflateError::InvalidHeader)?;
+            let end = rest
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or(InflateError::InvalidHeader)?;
+            pos += end + 1;
+        }
+    }
+    if flags & GZIP_FLAG_HCRC != 0 {
+        pos += 2;
+    }
+
+    if pos > data.len() {
+        return Err(InflateError::InvalidHeader);
+    }
+    Ok(pos)
+}
+
+/// Decompresses a gzip member, checking the CRC32 and size in its trailer.
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, InflateError> {
+    let start = gzip_header_len(data)?;
+    let mut output = Vec::new();
+    let end = start + inflate_into(&data[start..], &mut output)?;
+
+    let trailer = data.get(end..end + 8).ok_or(InflateError::UnexpectedEof)?;
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+    if crypto::crc32(&output) != crc {
+        return Err(InflateError::ChecksumMismatch);
+    }
+    if output.len() as u32 != size {
+        return Err(InflateError::SizeMismatch);
+    }
+    Ok(output)
+}
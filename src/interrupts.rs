@@ -1,17 +1,82 @@
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
-use super::{debug, error, print};
+use super::io::ports::PicMasks;
+use super::{cover, debug, error, kbug};
 use super::{gdt, hlt_loop};
 use lazy_static::lazy_static;
 
+use core::sync::atomic::{AtomicUsize, Ordering};
 use pic8259_simple::ChainedPics;
 use spin;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 
+const PIC_CASCADE_IRQ: u8 = 2;
+
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+static PIC_MASKS: spin::Mutex<PicMasks> = spin::Mutex::new(unsafe { PicMasks::new() });
+
+/// CPU exceptions we have a handler for, by vector number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Exception {
+    DivideError = 0,
+    Breakpoint = 3,
+    InvalidOpcode = 6,
+    DoubleFault = 8,
+    GeneralProtectionFault = 13,
+    PageFault = 14,
+}
+
+const EXCEPTION_VECTORS: usize = 32;
+
+static EXCEPTION_COUNTS: [AtomicUsize; EXCEPTION_VECTORS] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+impl Exception {
+    /// Number of times this exception was taken since boot.
+    pub fn count(self) -> usize {
+        EXCEPTION_COUNTS[self as usize].load(Ordering::Relaxed)
+    }
+
+    fn record(self) {
+        EXCEPTION_COUNTS[self as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Number of interrupt handlers currently running, nested ones included.
+static NESTING: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts the handler it is created in as running until it is dropped.
+struct HandlerGuard;
+
+impl HandlerGuard {
+    fn enter() -> HandlerGuard {
+        NESTING.fetch_add(1, Ordering::Relaxed);
+        HandlerGuard
+    }
+}
+
+impl Drop for HandlerGuard {
+    fn drop(&mut self) {
+        NESTING.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Interrupt nesting level, 0 outside interrupt handlers.
+pub fn nesting() -> usize {
+    NESTING.load(Ordering::Relaxed)
+}
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
@@ -21,6 +86,7 @@ pub enum InterruptIndex {
     PageFault,
     Timer = PIC_1_OFFSET,
     Keyboard = PIC_1_OFFSET + 1,
+    Serial = PIC_1_OFFSET + 4,
 }
 
 impl InterruptIndex {
@@ -32,58 +98,119 @@ impl InterruptIndex {
         usize::from(self.as_u8())
     }
 
+    /// IRQ line of a hardware interrupt.
+    pub fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
+
     pub fn send_bye_signal(i: InterruptIndex) {
         unsafe {
             PICS.lock().notify_end_of_interrupt(i.as_u8());
         }
     }
 
+    /// Returns true if the interrupted code was running in ring 3, from the
+    /// requested privilege level of the saved code segment.
+    fn from_userspace(stack_frame: &InterruptStackFrame) -> bool {
+        stack_frame.code_segment & 0b11 == 3
+    }
+
+    /// Faults caused by the interrupted code itself : the offending process
+    /// would be terminated if it came from userspace, only kernel faults are fatal.
+    fn fault(name: &str, stack_frame: &InterruptStackFrame) -> ! {
+        if InterruptIndex::from_userspace(stack_frame) {
+            cover!("fault_from_userspace");
+            error!(
+                "{} in userspace at $0C{:?}",
+                name, stack_frame.instruction_pointer
+            );
+            // there is no process layer yet to kill the offender and signal it
+            error!("No process to terminate, halting");
+            hlt_loop();
+        }
+        error!("{}", name);
+        error!(" $0C{:#?}", stack_frame);
+        hlt_loop();
+    }
+
+    extern "x86-interrupt" fn divide_error(stack_frame: &mut InterruptStackFrame) {
+        let _guard = HandlerGuard::enter();
+        cover!("divide_error");
+        Exception::DivideError.record();
+        InterruptIndex::fault("DIVIDE ERROR", stack_frame);
+    }
+
+    extern "x86-interrupt" fn invalid_opcode(stack_frame: &mut InterruptStackFrame) {
+        let _guard = HandlerGuard::enter();
+        cover!("invalid_opcode");
+        Exception::InvalidOpcode.record();
+        InterruptIndex::fault("INVALID OPCODE", stack_frame);
+    }
+
+    extern "x86-interrupt" fn general_protection_fault(
+        stack_frame: &mut InterruptStackFrame,
+        error_code: u64,
+    ) {
+        let _guard = HandlerGuard::enter();
+        cover!("general_protection_fault");
+        Exception::GeneralProtectionFault.record();
+        error!("Error Code: $0C{:#x}", error_code);
+        InterruptIndex::fault("GENERAL PROTECTION FAULT", stack_frame);
+    }
+
     extern "x86-interrupt" fn page_fault(
         stack_frame: &mut InterruptStackFrame,
         error_code: PageFaultErrorCode,
     ) {
         use x86_64::registers::control::Cr2;
 
+        let _guard = HandlerGuard::enter();
+        cover!("page_fault");
+        Exception::PageFault.record();
+
+        if InterruptIndex::from_userspace(stack_frame) {
+            error!("Accessed Address: $0C{:?}", Cr2::read());
+            InterruptIndex::fault("PAGE FAULT", stack_frame);
+        }
+
         error!("PAGE FAULT");
         error!("Accessed Address: $0C{:?}", Cr2::read());
+        if let Some(guard) = crate::stack::guard_page_of(Cr2::read()) {
+            error!("Guard page $0C{:?}$!: kernel stack overflow", guard);
+        }
         error!("Error Code: $0C{:?}", error_code);
         error!(" $0C{:#?}", stack_frame);
         hlt_loop();
     }
 
     extern "x86-interrupt" fn timer(_stack_frame: &mut InterruptStackFrame) {
+        let _guard = HandlerGuard::enter();
         //print!(".");
+        crate::time::tick();
+        crate::replay::tick();
+        crate::screensaver::tick();
         InterruptIndex::send_bye_signal(InterruptIndex::Timer);
     }
 
     extern "x86-interrupt" fn keyboard(_stack_frame: &mut InterruptStackFrame) {
-        use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-        use spin::Mutex;
-        use x86_64::instructions::port::Port;
-
-        lazy_static! {
-            static ref KEYBOARD: Mutex<Keyboard<layouts::Azerty, ScancodeSet1>> = Mutex::new(
-                Keyboard::new(layouts::Azerty, ScancodeSet1, HandleControl::Ignore)
-            );
-        }
-
-        let mut keyboard = KEYBOARD.lock();
-        let mut port = Port::new(0x60);
-
-        let scancode: u8 = unsafe { port.read() };
-        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(character) => print!("{}", character),
-                    DecodedKey::RawKey(key) => print!("{:?}", key),
-                }
-            }
+        let _guard = HandlerGuard::enter();
+        if let Some(scancode) = crate::ps2::read_scancode() {
+            crate::keyboard::add_scancode(scancode);
         }
 
         InterruptIndex::send_bye_signal(InterruptIndex::Keyboard);
     }
 
+    extern "x86-interrupt" fn serial(_stack_frame: &mut InterruptStackFrame) {
+        let _guard = HandlerGuard::enter();
+        crate::serial::interrupt();
+        InterruptIndex::send_bye_signal(InterruptIndex::Serial);
+    }
+
     extern "x86-interrupt" fn breakpoint(stack_frame: &mut InterruptStackFrame) {
+        let _guard = HandlerGuard::enter();
+        cover!("breakpoint");
+        Exception::Breakpoint.record();
         error!("BREAKPOINT\n{:#?}", stack_frame);
     }
 
@@ -91,8 +218,11 @@ impl InterruptIndex {
         stack_frame: &mut InterruptStackFrame,
         _error_code: u64,
     ) -> ! {
+        let _guard = HandlerGuard::enter();
+        cover!("double_fault");
+        Exception::DoubleFault.record();
         error!("DOUBLE-FAULT:\n{:#?}", stack_frame);
-        panic!("$0CCan't continue on double fault.");
+        kbug!("Can't continue on double fault");
     }
 }
 
@@ -109,16 +239,48 @@ lazy_static! {
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
         idt.page_fault.set_handler_fn(InterruptIndex::page_fault);
+        idt.divide_error.set_handler_fn(InterruptIndex::divide_error);
+        idt.invalid_opcode.set_handler_fn(InterruptIndex::invalid_opcode);
+        idt.general_protection_fault
+            .set_handler_fn(InterruptIndex::general_protection_fault);
 
         // interupts
 
 
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(InterruptIndex::timer);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(InterruptIndex::keyboard);
+        idt[InterruptIndex::Serial.as_usize()].set_handler_fn(InterruptIndex::serial);
         idt
     };
 }
 
+/// Masks every IRQ line of both PICs.
+///
+/// `ChainedPics::initialize` restores the masks it finds, so lines stay masked
+/// across it until `unmask_irq` enables the ones we have a handler for.
+pub fn mask_all_irqs() {
+    x86_64::instructions::interrupts::without_interrupts(|| PIC_MASKS.lock().write(0xFFFF));
+}
+
+fn update_masks(update: impl FnOnce(u16) -> u16) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut masks = PIC_MASKS.lock();
+        let current = masks.read();
+        masks.write(update(current));
+    });
+}
+
+pub fn unmask_irq(irq: u8) {
+    update_masks(|masks| masks & !(1 << irq));
+    if irq >= 8 {
+        unmask_irq(PIC_CASCADE_IRQ);
+    }
+}
+
+pub fn mask_irq(irq: u8) {
+    update_masks(|masks| masks | (1 << irq));
+}
+
 pub fn init_idt() {
     debug!("Initialisation of the IDT");
     IDT.load();
@@ -126,6 +288,9 @@ pub fn init_idt() {
 
 #[test_case]
 fn test_breakpoint_exception() {
+    let before = Exception::Breakpoint.count();
     // invoke a breakpoint exception
     x86_64::instructions::interrupts::int3();
+    assert_eq!(Exception::Breakpoint.count(), before + 1);
+    assert_eq!(Exception::PageFault.count(), 0);
 }
@@ -0,0 +1,3 @@
+//! Access to the machine's I/O devices.
+
+pub mod ports;
@@ -0,0 +1,395 @@
+//! The legacy devices at fixed I/O ports, one typed struct each.
+//!
+//! A raw `Port` can be made anywhere, so two modules driving the same device
+//! could interleave an index/data pair or steal each other's replies. Here the
+//! constructors are `unsafe` and the invariant they ask for is ownership : only
+//! one instance of each device exists, made once by the module driving it and
+//! kept behind a lock, and every other user goes through that module.
+//!
+//! | device | ports | owner |
+//! |---|---|---|
+//! | `Ps2Controller` | 0x60, 0x64 | `ps2::CONTROLLER` |
+//! | `Pit` | 0x40, 0x42, 0x43 | `time::PIT` |
+//! | `Cmos` | 0x70, 0x71 | `rtc::CMOS` |
+//! | `PicMasks` | 0x21, 0xA1 | `interrupts::PIC_MASKS` |
+//! | `VgaCrtc` | 0x3D4, 0x3D5 | `vga_writer::CRTC` |
+//! | `VgaSequencer` | 0x3C4, 0x3C5 | `screensaver::SEQUENCER` |
+//! | `Uart` | 0x3F8 to 0x3FD (COM1) | `serial::TX` |
+//! | `DebugExit` | 0xF4 | `serial::DEBUG_EXIT` |
+//! | `PowerOff` | 0x604, 0xB004, 0x4004 | `power::POWER_OFF` |
+//!
+//! Once a device is owned, accesses that only affect the device itself are
+//! safe. The ones that can change how memory is seen (the 8042 output port
+//! drives the A20 gate, the VGA registers map the frame buffer) stay `unsafe`.
+
+use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
+
+/// The 8042 PS/2 controller.
+pub struct Ps2Controller {
+    data: Port<u8>,
+    status: PortReadOnly<u8>,
+    command: PortWriteOnly<u8>,
+}
+
+impl Ps2Controller {
+    /// This function is unsafe because the caller must guarantee that only one
+    /// exists : the controller's replies come through the same data port as the
+    /// keyboard bytes, so any other reader would take them.
+    pub const unsafe fn new() -> Ps2Controller {
+        Ps2Controller {
+            data: Port::new(0x60),
+            status: PortReadOnly::new(0x64),
+            command: PortWriteOnly::new(0x64),
+        }
+    }
+
+    pub fn status(&mut self) -> u8 {
+        unsafe { self.status.read() }
+    }
+
+    pub fn read_data(&mut self) -> u8 {
+        unsafe { self.data.read() }
+    }
+
+    /// This function is unsafe because some commands reset the machine or write
+    /// the controller output port, which holds the A20 gate : the caller must
+    /// guarantee that their effects are intended.
+    pub unsafe fn write_command(&mut self, command: u8) {
+        self.command.write(command)
+    }
+
+    /// This function is unsafe because what a data byte does depends on the
+    /// command before it : the caller must guarantee the same as for
+    /// `write_command`.
+    pub unsafe fn write_data(&mut self, value: u8) {
+        self.data.write(value)
+    }
+}
+
+/// Channels of the PIT that can still be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PitChannel {
+    /// Wired to IRQ 0.
+    Timer = 0,
+    /// Drives the PC speaker, behind the gate of port 0x61.
+    Speaker = 2,
+}
+
+/// Counting modes of a PIT channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PitMode {
+    /// Raises the output once when the count reaches 0.
+    InterruptOnTerminalCount = 0,
+    /// Periodic, what the BIOS sets for the timer.
+    SquareWave = 3,
+}
+
+/// Command byte selecting `channel` and `mode`, count written low byte first.
+const fn pit_command(channel: PitChannel, mode: PitMode) -> u8 {
+    const ACCESS_LOW_THEN_HIGH: u8 = 0b11 << 4;
+    (channel as u8) << 6 | ACCESS_LOW_THEN_HIGH | (mode as u8) << 1
+}
+
+/// The 8253/8254 programmable interval timer.
+pub struct Pit {
+    timer: Port<u8>,
+    speaker: Port<u8>,
+    command: PortWriteOnly<u8>,
+}
+
+impl Pit {
+    /// This function is unsafe because the caller must guarantee that only one
+    /// exists : a count is written as two bytes after a command, which must not
+    /// be interleaved with another programming of the PIT.
+    pub const unsafe fn new() -> Pit {
+        Pit {
+            timer: Port::new(0x40),
+            speaker: Port::new(0x42),
+            command: PortWriteOnly::new(0x43),
+        }
+    }
+
+    /// Starts `channel` counting down from `count` input clocks, 0 meaning 65536.
+    pub fn program(&mut self, channel: PitChannel, mode: PitMode, count: u16) {
+        let data = match channel {
+            PitChannel::Timer => &mut self.timer,
+            PitChannel::Speaker => &mut self.speaker,
+        };
+        unsafe {
+            self.command.write(pit_command(channel, mode));
+            data.write(count as u8);
+            data.write((count >> 8) as u8);
+        }
+    }
+}
+
+/// The CMOS memory holding the RTC registers.
+pub struct Cmos {
+    address: PortWriteOnly<u8>,
+    data: Port<u8>,
+}
+
+impl Cmos {
+    /// Bit 7 of the address port also controls NMIs, keep them enabled.
+    const NMI_ENABLED: u8 = 0x00;
+
+    /// This function is unsafe because the caller must guarantee that only one
+    /// exists : a read selects the register on the address port first, and must
+    /// not be interleaved with another access.
+    pub const unsafe fn new() -> Cmos {
+        Cmos {
+            address: PortWriteOnly::new(0x70),
+            data: Port::new(0x71),
+        }
+    }
+
+    pub fn read(&mut self, register: u8) -> u8 {
+        unsafe {
+            self.address.write(Self::NMI_ENABLED | register);
+            self.data.read()
+        }
+    }
+}
+
+/// The interrupt mask registers of the two 8259 PICs.
+///
+/// `pic8259_simple::ChainedPics` also writes these ports, in `initialize` only,
+/// and puts back the masks it found there.
+pub struct PicMasks {
+    master: Port<u8>,
+    slave: Port<u8>,
+}
+
+impl PicMasks {
+    /// This function is unsafe because the caller must guarantee that only one
+    /// exists : changing a mask is a read-modify-write.
+    pub const unsafe fn new() -> PicMasks {
+        PicMasks {
+            master: Port::new(0x21),
+            slave: Port::new(0xA1),
+        }
+    }
+
+    /// Masks of IRQ 0 to 15, a set bit is a masked line.
+    pub fn read(&mut self) -> u16 {
+        unsafe { u16::from(self.slave.read()) << 8 | u16::from(self.master.read()) }
+    }
+
+    pub fn write(&mut self, masks: u16) {
+        unsafe {
+            self.master.write(masks as u8);
+            self.slave.write((masks >> 8) as u8);
+        }
+    }
+}
+
+/// A 8250/16550 serial port.
+pub struct Uart {
+    data: Port<u8>,
+    interrupt_enable: Port<u8>,
+    /// Reads the interrupt identification, writes the FIFO control.
+    interrupt_id: PortReadOnly<u8>,
+    fifo_control: PortWriteOnly<u8>,
+    line_control: Port<u8>,
+    modem_control: Port<u8>,
+    line_status: PortReadOnly<u8>,
+}
+
+impl Uart {
+    const LINE_CONTROL_DLAB: u8 = 1 << 7;
+    const LINE_CONTROL_8N1: u8 = 0b11;
+    /// Enable and clear both FIFOs, interrupt at 14 received bytes.
+    const FIFO_CONTROL_ENABLE: u8 = 0xC7;
+    /// DTR, RTS, and OUT2 which gates the IRQ line.
+    const MODEM_CONTROL_IRQ: u8 = 0x0B;
+    /// Bits 6 and 7 of the interrupt identification, both set when the FIFOs
+    /// are enabled and work (16550A and later).
+    const INTERRUPT_ID_FIFO: u8 = 0b1100_0000;
+    /// Divisor of the 115200 Hz clock, 38400 baud.
+    const DIVISOR: u16 = 3;
+
+    /// Bytes the transmit FIFO of a 16550A holds.
+    pub const FIFO_SIZE: usize = 16;
+
+    /// This function is unsafe because the caller must guarantee that a UART is
+    /// at `base` and that only one `Uart` exists for it : the line status
+    /// register is polled between writes.
+    pub const unsafe fn new(base: u16) -> Uart {
+        Uart {
+            data: Port::new(base),
+            interrupt_enable: Port::new(base + 1),
+            interrupt_id: PortReadOnly::new(base + 2),
+            fifo_control: PortWriteOnly::new(base + 2),
+            line_control: Port::new(base + 3),
+            modem_control: Port::new(base + 4),
+            line_status: PortReadOnly::new(base + 5),
+        }
+    }
+
+    /// Sets 38400 baud 8N1 and the FIFOs, interrupts disabled. Returns the
+    /// size of the transmit FIFO : 1 on the older UARTs without a working one.
+    pub fn init(&mut self) -> usize {
+        unsafe {
+            self.interrupt_enable.write(0);
+            self.line_control.write(Self::LINE_CONTROL_DLAB);
+            self.data.write(Self::DIVISOR as u8);
+            self.interrupt_enable.write((Self::DIVISOR >> 8) as u8);
+            self.line_control.write(Self::LINE_CONTROL_8N1);
+            self.fifo_control.write(Self::FIFO_CONTROL_ENABLE);
+            self.modem_control.write(Self::MODEM_CONTROL_IRQ);
+            if self.interrupt_id.read() & Self::INTERRUPT_ID_FIFO == Self::INTERRUPT_ID_FIFO {
+                Self::FIFO_SIZE
+            } else {
+                1
+            }
+        }
+    }
+
+    pub fn line_status(&mut self) -> u8 {
+        unsafe { self.line_status.read() }
+    }
+
+    pub fn read_data(&mut self) -> u8 {
+        unsafe { self.data.read() }
+    }
+
+    pub fn write_data(&mut self, byte: u8) {
+        unsafe { self.data.write(byte) }
+    }
+
+    pub fn set_interrupts(&mut self, flags: u8) {
+        unsafe { self.interrupt_enable.write(flags) }
+    }
+}
+
+/// QEMU's `isa-debug-exit` device : writing a code ends the emulator with
+/// status `(code << 1) | 1`. Nothing listens there on real hardware.
+pub struct DebugExit {
+    port: PortWriteOnly<u32>,
+}
+
+impl DebugExit {
+    /// This function is unsafe because the caller must guarantee that only one
+    /// exists and that QEMU runs with the device at port 0xF4 : something else
+    /// could listen there otherwise.
+    pub const unsafe fn new() -> DebugExit {
+        DebugExit {
+            port: PortWriteOnly::new(0xF4),
+        }
+    }
+
+    pub fn exit(&mut self, code: u32) {
+        unsafe { self.port.write(code) }
+    }
+}
+
+/// The fixed poweroff ports of the emulators, without ACPI.
+pub struct PowerOff {
+    ports: [(PortWriteOnly<u16>, u16); 3],
+}
+
+impl PowerOff {
+    /// This function is unsafe because the caller must guarantee that only one
+    /// exists and that only the poweroff path uses it : on real hardware these
+    /// ports can belong to anything.
+    pub const unsafe fn new() -> PowerOff {
+        PowerOff {
+            ports: [
+                (PortWriteOnly::new(0x604), 0x2000),  // QEMU
+                (PortWriteOnly::new(0xB004), 0x2000), // Bochs, older QEMU
+                (PortWriteOnly::new(0x4004), 0x3400), // VirtualBox
+            ],
+        }
+    }
+
+    /// Tries every port. Returns if the machine is still running.
+    pub fn power_off(&mut self) {
+        for (port, value) in self.ports.iter_mut() {
+            unsafe { port.write(*value) };
+        }
+    }
+}
+
+/// An index port selecting which register the data port accesses.
+struct IndexedRegisters {
+    index: Port<u8>,
+    data: Port<u8>,
+}
+
+impl IndexedRegisters {
+    const fn new(index: u16) -> IndexedRegisters {
+        IndexedRegisters {
+            index: Port::new(index),
+            data: Port::new(index + 1),
+        }
+    }
+
+    fn read(&mut self, register: u8) -> u8 {
+        unsafe {
+            self.index.write(register);
+            self.data.read()
+        }
+    }
+
+    unsafe fn write(&mut self, register: u8, value: u8) {
+        self.index.write(register);
+        self.data.write(value);
+    }
+}
+
+/// The VGA CRT controller, in color mode.
+pub struct VgaCrtc(IndexedRegisters);
+
+impl VgaCrtc {
+    /// This function is unsafe because the caller must guarantee that only one
+    /// exists : every access selects the register on the index port first.
+    pub const unsafe fn new() -> VgaCrtc {
+        VgaCrtc(IndexedRegisters::new(0x3D4))
+    }
+
+    pub fn read(&mut self, register: u8) -> u8 {
+        self.0.read(register)
+    }
+
+    /// This function is unsafe because some registers move the start of the
+    /// frame buffer or change its geometry : the caller must guarantee that the
+    /// console still matches.
+    pub unsafe fn write(&mut self, register: u8, value: u8) {
+        self.0.write(register, value)
+    }
+}
+
+/// The VGA sequencer.
+pub struct VgaSequencer(IndexedRegisters);
+
+impl VgaSequencer {
+    /// This function is unsafe because the caller must guarantee that only one
+    /// exists : every access selects the register on the index port first.
+    pub const unsafe fn new() -> VgaSequencer {
+        VgaSequencer(IndexedRegisters::new(0x3C4))
+    }
+
+    pub fn read(&mut self, register: u8) -> u8 {
+        self.0.read(register)
+    }
+
+    /// This function is unsafe because the memory mode register changes how the
+    /// frame buffer is addressed : the caller must guarantee that the console
+    /// still matches.
+    pub unsafe fn write(&mut self, register: u8, value: u8) {
+        self.0.write(register, value)
+    }
+}
+
+#[test_case]
+fn test_pit_command_bytes() {
+    // what the timer code used to write by hand
+    assert_eq!(
+        pit_command(PitChannel::Timer, PitMode::InterruptOnTerminalCount),
+        0b0011_0000
+    );
+    assert_eq!(pit_command(PitChannel::Timer, PitMode::SquareWave), 0b0011_0110);
+    assert_eq!(pit_command(PitChannel::Speaker, PitMode::SquareWave), 0b1011_0110);
+}
@@ -0,0 +1,204 @@
+//! Keyboard decoding, fed with scancodes by the IRQ1 handler (or `inject`).
+//!
+//! Decoded characters are echoed on the console and queued in an input buffer
+//! that readers drain with `read_char`.
+//!
+//! On top of the AZERTY decoding, the `^` key is a dead key : `^` then `e` gives
+//! `ê`, `¨` (with shift) then `e` gives `ë`, anything that doesn't compose is
+//! written after the accent and space gives the accent alone. AltGr (right Alt)
+//! reaches the third level of the number row (`@`, `#`, `{`...) through the
+//! crate's layout, which only lacks `€` on AltGr+E.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use pc_keyboard::{
+    layouts, DecodedKey, HandleControl, KeyCode, KeyEvent, Keyboard, KeyboardLayout, Modifiers,
+    ScancodeSet1,
+};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{print, screensaver, warn_once};
+
+/// The crate's AZERTY layout, with `€` on AltGr+E.
+pub struct Layout;
+
+impl KeyboardLayout for Layout {
+    fn map_keycode(
+        keycode: KeyCode,
+        modifiers: &Modifiers,
+        handle_ctrl: HandleControl,
+    ) -> DecodedKey {
+        if keycode == KeyCode::E && modifiers.alt_gr {
+            DecodedKey::Unicode('€')
+        } else {
+            layouts::Azerty::map_keycode(keycode, modifiers, handle_ctrl)
+        }
+    }
+}
+
+lazy_static! {
+    static ref KEYBOARD: Mutex<Keyboard<Layout, ScancodeSet1>> =
+        Mutex::new(Keyboard::new(Layout, ScancodeSet1, HandleControl::Ignore));
+}
+
+pub const INPUT_CAPACITY: usize = 256;
+
+/// Whether decoded keys are printed on the console (`keyboard.echo`, 0 or 1).
+pub static ECHO: AtomicUsize = AtomicUsize::new(1);
+
+/// Ring of decoded characters, the oldest is dropped when full.
+struct InputBuffer {
+    chars: [char; INPUT_CAPACITY],
+    start: usize,
+    len: usize,
+}
+
+impl InputBuffer {
+    fn push(&mut self, character: char) {
+        let end = (self.start + self.len) % INPUT_CAPACITY;
+        self.chars[end] = character;
+        if self.len == INPUT_CAPACITY {
+            warn_once!("keyboard input buffer full, dropping the oldest characters");
+            self.start = (self.start + 1) % INPUT_CAPACITY;
+        } else {
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<char> {
+        if self.len == 0 {
+            return None;
+        }
+        let character = self.chars[self.start];
+        self.start = (self.start + 1) % INPUT_CAPACITY;
+        self.len -= 1;
+        Some(character)
+    }
+}
+
+static INPUT: Mutex<InputBuffer> = Mutex::new(InputBuffer {
+    chars: ['\0'; INPUT_CAPACITY],
+    start: 0,
+    len: 0,
+});
+
+/// Accent of the dead key pressed last, waiting for the next character.
+static DEAD_KEY: Mutex<Option<char>> = Mutex::new(None);
+
+fn is_accent(character: char) -> bool {
+    character == '^' || character == '¨'
+}
+
+/// Combines a dead key accent with the next character.
+fn compose(accent: char, character: char) -> Option<char> {
+    let (from, to) = match accent {
+        '^' => ("aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+        '¨' => ("aeiouyAEIOU", "äëïöüÿÄËÏÖÜ"),
+        _ => return None,
+    };
+    from.chars()
+        .zip(to.chars())
+        .find(|&(plain, _)| plain == character)
+        .map(|(_, composed)| composed)
+}
+
+fn emit(character: char) {
+    if ECHO.load(Ordering::Relaxed) != 0 {
+        // a typed `$` is text, not a color escape
+        match character {
+            '$' => print!("{}", "$$"),
+            _ => print!("{}", character),
+        }
+    }
+    INPUT.lock().push(character);
+}
+
+/// Runs a decoded character through the dead key state.
+fn emit_composed(character: char, from_dead_key: bool) {
+    let mut dead_key = DEAD_KEY.lock();
+    match dead_key.take() {
+        None if from_dead_key => *dead_key = Some(character),
+        None => emit(character),
+        Some(accent) => match compose(accent, character) {
+            Some(composed) => emit(composed),
+            // space or the same dead key again gives the accent alone
+            None if character == ' ' || (from_dead_key && character == accent) => emit(accent),
+            None => {
+                emit(accent);
+                if from_dead_key {
+                    *dead_key = Some(character);
+                } else {
+                    emit(character);
+                }
+            }
+        },
+    }
+}
+
+fn process_key_event(keyboard: &mut Keyboard<Layout, ScancodeSet1>, event: KeyEvent) {
+    let is_dead_key = event.code == KeyCode::BracketSquareLeft;
+    match keyboard.process_keyevent(event) {
+        Some(DecodedKey::Unicode(character)) => {
+            emit_composed(character, is_dead_key && is_accent(character))
+        }
+        Some(DecodedKey::RawKey(key)) => {
+            if ECHO.load(Ordering::Relaxed) != 0 {
+                print!("{:?}", key);
+            }
+        }
+        None => {}
+    }
+}
+
+/// Feeds one scancode to the decoder. Called with interrupts disabled.
+pub(crate) fn add_scancode(scancode: u8) {
+    screensaver::wake();
+    let mut keyboard = KEYBOARD.lock();
+    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+        process_key_event(&mut keyboard, key_event);
+    }
+}
+
+/// Feeds a scancode as if it came from the keyboard controller.
+pub fn inject(scancode: u8) {
+    interrupts::without_interrupts(|| add_scancode(scancode));
+}
+
+/// Takes the oldest decoded character, if any.
+pub fn read_char() -> Option<char> {
+    interrupts::without_interrupts(|| INPUT.lock().pop())
+}
+
+#[test_case]
+fn test_injected_scancodes_are_decoded() {
+    while read_char().is_some() {}
+    // press and release the keys at the QWERTY 'q' and 'w' positions
+    for &scancode in &[0x10, 0x90, 0x11, 0x91] {
+        inject(scancode);
+    }
+    assert_eq!(read_char(), Some('a'));
+    assert_eq!(read_char(), Some('z'));
+    assert_eq!(read_char(), None);
+}
+
+#[test_case]
+fn test_dead_keys_and_alt_gr() {
+    while read_char().is_some() {}
+    let scancodes = [
+        0x1A, 0x9A, 0x12, 0x92, // ^ e
+        0x2A, 0x1A, 0x9A, 0xAA, 0x17, 0x97, // ¨ i
+        0x1A, 0x9A, 0x39, 0xB9, // ^ space
+        0x1A, 0x9A, 0x13, 0x93, // ^ r
+        0xE0, 0x38, 0x0B, 0x8B, 0xE0, 0xB8, // AltGr à
+        0xE0, 0x38, 0x12, 0x92, 0xE0, 0xB8, // AltGr e
+        0x12, 0x92, // e, AltGr released
+    ];
+    for &scancode in &scancodes {
+        inject(scancode);
+    }
+    for &expected in &['ê', 'ï', '^', '^', 'r', '@', '€', 'e'] {
+        assert_eq!(read_char(), Some(expected));
+    }
+    assert_eq!(read_char(), None);
+}
@@ -0,0 +1,212 @@
+//! Kernel image layout, from the symbols rust-lld defines for every ELF it links.
+//!
+//! Without a linker script the order is the lld default : ELF headers and
+//! `.rodata` (read only), `.text` (executable), then `.data` and `.bss`
+//! (writable). `etext`, `edata` and `end` mark the end of those, so the ranges
+//! below are exact at their ends and page aligned at their starts. There is no
+//! symbol between `.rodata` and `.text` : the ELF program headers, loaded
+//! right after the ELF header at `__ehdr_start`, give where each segment
+//! starts and ends instead.
+
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+use crate::memory;
+use crate::{debug, done, error, info};
+
+extern "C" {
+    static __ehdr_start: u8;
+    static etext: u8;
+    static edata: u8;
+    static __bss_start: u8;
+    static end: u8;
+}
+
+const PAGE_SIZE: u64 = 4096;
+
+// ELF64 header and program header fields used below
+const E_PHOFF: usize = 0x20;
+const E_PHENTSIZE: usize = 0x36;
+const E_PHNUM: usize = 0x38;
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Section {
+    pub name: &'static str,
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+}
+
+impl Section {
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+/// Loaded segments of the image : end of the read only one, start of the
+/// executable one. `None` if the headers don't have them.
+///
+/// This function is unsafe because the caller must guarantee that the ELF and
+/// program headers are mapped at `image_start`.
+unsafe fn segments(image_start: VirtAddr) -> (Option<VirtAddr>, Option<VirtAddr>) {
+    let header = image_start.as_ptr::<u8>();
+    let field = |offset: usize| header.add(offset);
+    let phoff = (field(E_PHOFF) as *const u64).read_unaligned() as usize;
+    let phentsize = (field(E_PHENTSIZE) as *const u16).read_unaligned() as usize;
+    let phnum = (field(E_PHNUM) as *const u16).read_unaligned() as usize;
+
+    let (mut rodata_end, mut text_start) = (None, None);
+    for index in 0..phnum {
+        let entry = field(phoff + index * phentsize);
+        let p_type = (entry as *const u32).read_unaligned();
+        let p_flags = (entry.add(4) as *const u32).read_unaligned();
+        let p_vaddr = (entry.add(16) as *const u64).read_unaligned();
+        let p_memsz = (entry.add(40) as *const u64).read_unaligned();
+        if p_type != PT_LOAD {
+            continue;
+        }
+        if p_flags & (PF_X | PF_W) == 0 && rodata_end.is_none() {
+            rodata_end = Some(VirtAddr::new(p_vaddr + p_memsz));
+        } else if p_flags & PF_X != 0 && text_start.is_none() {
+            text_start = Some(VirtAddr::new(p_vaddr));
+        }
+    }
+    (rodata_end, text_start)
+}
+
+/// Returns the `rodata` (headers included), `text`, `data` and `bss` ranges.
+pub fn sections() -> [Section; 4] {
+    let addr = |symbol: &u8| VirtAddr::new(symbol as *const u8 as u64);
+    let (image_start, text_end, data_end, bss_start, image_end) = unsafe {
+        (
+            addr(&__ehdr_start),
+            addr(&etext),
+            addr(&edata),
+            addr(&__bss_start),
+            addr(&end),
+        )
+    };
+    // the headers are part of the first segment, which the bootloader maps
+    let (rodata_end, text_start) = unsafe { segments(image_start) };
+    let text_start = text_start.unwrap_or(image_start);
+    [
+        Section {
+            name: "rodata",
+            start: image_start,
+            end: rodata_end.unwrap_or(text_start),
+        },
+        Section {
+            name: "text",
+            start: text_start,
+            end: text_end,
+        },
+        Section {
+            name: "data",
+            start: text_end.align_up(PAGE_SIZE),
+            end: data_end,
+        },
+        Section {
+            name: "bss",
+            start: bss_start,
+            end: image_end,
+        },
+    ]
+}
+
+/// Returns the whole kernel image range.
+pub fn image() -> Section {
+    let sections = sections();
+    Section {
+        name: "kernel",
+        start: sections[0].start,
+        end: sections[3].end,
+    }
+}
+
+/// Logs the mapped ranges of the kernel image with their flags, grouping pages
+/// with identical flags, and checks that no page is both writable and executable
+/// and that `data`/`bss` can not be executed. Returns false on any violation.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `physical_memory_offset`.
+pub unsafe fn report(physical_memory_offset: VirtAddr) -> bool {
+    let sections = sections();
+    let image = image();
+    info!(
+        "Kernel image {:#x}..{:#x} ({} KiB)",
+        image.start.as_u64(),
+        image.end.as_u64(),
+        image.size() / 1024
+    );
+    for section in sections.iter() {
+        debug!(
+            "  {:6} {:#x}..{:#x} ({} bytes)",
+            section.name,
+            section.start.as_u64(),
+            section.end.as_u64(),
+            section.size()
+        );
+    }
+
+    let mut valid = true;
+    let mut run_start = image.start.align_down(PAGE_SIZE);
+    let mut run_flags = None;
+    let mut page = run_start;
+    while page < image.end {
+        let flags = memory::translate_flags(page, physical_memory_offset);
+        valid &= check_page(page, flags, &sections[1]);
+        if flags != run_flags {
+            print_run(run_start, page, run_flags);
+            run_start = page;
+            run_flags = flags;
+        }
+        page += PAGE_SIZE;
+    }
+    print_run(run_start, page, run_flags);
+
+    if valid {
+        done!("Kernel mapping is W^X");
+    }
+    valid
+}
+
+fn check_page(page: VirtAddr, flags: Option<PageTableFlags>, text: &Section) -> bool {
+    let flags = match flags {
+        Some(flags) => flags,
+        None => return true, // padding between segments
+    };
+    let writable = flags.contains(PageTableFlags::WRITABLE);
+    let executable = !flags.contains(PageTableFlags::NO_EXECUTE);
+
+    if writable && executable {
+        error!("$0CPage {:#x} is writable and executable", page.as_u64());
+        false
+    } else if writable && page < text.end {
+        error!("$0CPage {:#x} of the rodata or text section is writable", page.as_u64());
+        false
+    } else if executable && page >= text.end.align_up(PAGE_SIZE) {
+        error!("$0CPage {:#x} of the data sections is executable", page.as_u64());
+        false
+    } else {
+        true
+    }
+}
+
+fn print_run(start: VirtAddr, end: VirtAddr, flags: Option<PageTableFlags>) {
+    if start == end {
+        return;
+    }
+    match flags {
+        Some(flags) => debug!(
+            "  mapped {:#x}..{:#x} {}{}",
+            start.as_u64(),
+            end.as_u64(),
+            if flags.contains(PageTableFlags::WRITABLE) { "W" } else { "R" },
+            if flags.contains(PageTableFlags::NO_EXECUTE) { "" } else { "X" }
+        ),
+        None => debug!("  unmapped {:#x}..{:#x}", start.as_u64(), end.as_u64()),
+    }
+}
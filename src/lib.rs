@@ -17,6 +17,9 @@ extern crate alloc;
 #[macro_use]
 pub mod serial;
 #[macro_use]
+pub mod color;
+pub mod io;
+#[macro_use]
 pub mod vga_writer;
 #[macro_use]
 pub mod gdt;
@@ -24,10 +27,35 @@ pub mod gdt;
 pub mod interrupts;
 #[macro_use]
 pub mod memory;
+#[cfg(feature = "memtest")]
+pub mod memtest;
 #[macro_use]
 pub mod logger;
 #[macro_use]
 pub mod allocator;
+pub mod crypto;
+pub mod inflate;
+pub mod layout;
+pub mod vm;
+pub mod stack;
+pub mod time;
+pub mod rtc;
+pub mod ps2;
+pub mod events;
+pub mod cpu;
+pub mod driver;
+pub mod power;
+#[macro_use]
+pub mod bug;
+pub mod preempt;
+#[macro_use]
+pub mod coverage;
+pub mod keyboard;
+pub mod replay;
+pub mod sysctl;
+pub mod screensaver;
+#[cfg(feature = "selftest")]
+pub mod selftest;
 
 #[macro_use]
 pub mod testing;
@@ -54,9 +82,24 @@ pub fn hlt_loop() -> ! {
 pub fn stage1() {
     debug!("Stage 1...");
     gdt::init();
+    interrupts::mask_all_irqs();
     interrupts::init_idt();
+    events::subscribe(events::log_event).expect("event bus full");
+    driver::register(&serial::SERIAL_DRIVER).expect("driver registry full");
+    let has_keyboard = ps2::init_or_warn();
+    if has_keyboard {
+        driver::register(&ps2::KEYBOARD_DRIVER).expect("driver registry full");
+        events::publish(events::Event::DeviceAttached(events::Device::Ps2Keyboard));
+    }
+    time::init();
     debug!("Enabling interrupts");
     unsafe { interrupts::PICS.lock().initialize() };
+    interrupts::unmask_irq(interrupts::InterruptIndex::Timer.irq());
+    interrupts::unmask_irq(interrupts::InterruptIndex::Serial.irq());
+    serial::enable_interrupt_tx();
+    if has_keyboard {
+        interrupts::unmask_irq(interrupts::InterruptIndex::Keyboard.irq());
+    }
     x86_64::instructions::interrupts::enable();
     done!("Stage 1");
 }
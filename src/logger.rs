@@ -1,76 +1,156 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(usize)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+}
+
+/// Where log messages go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sink {
+    Vga,
+    Serial,
+}
+
+/// Messages of a level above this one are dropped everywhere (`kernel.log_level`).
+pub static LOG_LEVEL: AtomicUsize = AtomicUsize::new(Level::Debug as usize);
+/// Same for the screen only (`console.log_level`), to keep it readable.
+pub static VGA_LOG_LEVEL: AtomicUsize = AtomicUsize::new(Level::Debug as usize);
+/// Same for serial only (`serial.log_level`).
+pub static SERIAL_LOG_LEVEL: AtomicUsize = AtomicUsize::new(Level::Debug as usize);
+
+#[doc(hidden)]
+pub fn enabled(sink: Sink, level: Level) -> bool {
+    let sink_level = match sink {
+        Sink::Vga => &VGA_LOG_LEVEL,
+        Sink::Serial => &SERIAL_LOG_LEVEL,
+    };
+    level as usize <= LOG_LEVEL.load(Ordering::Relaxed)
+        && level as usize <= sink_level.load(Ordering::Relaxed)
+}
+
 #[macro_export]
 macro_rules! println {
     () => ($crate::print!("\n"));
-    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+    ($fmt:expr $(, $($arg:tt)*)?) => ($crate::print!(concat!($fmt, "\n") $(, $($arg)*)?));
 }
 
 #[cfg(feature = "qemu-connect")]
 #[macro_export]
 macro_rules! print {
-    ($($arg:tt)*) => ({
-        $crate::vga_writer::_print(format_args!($($arg)*));
-        $crate::serial::_print(format_args!($($arg)*));
+    ($fmt:expr $(, $($arg:tt)*)?) => ({
+        $crate::check_colors!($fmt);
+        $crate::vga_writer::_print(format_args!($fmt $(, $($arg)*)?));
+        $crate::serial::_print(format_args!($fmt $(, $($arg)*)?));
     });
 }
 #[cfg(feature = "qemu-connect")]
 #[macro_export]
 macro_rules! debug {
-    ($($arg:tt)*) => ({
-        $crate::vga_writer::_print(format_args!("[$05DBUG$!] "));
-        $crate::vga_writer::_print(format_args!($($arg)*));
-        $crate::vga_writer::_print(format_args!("\n"));
-        $crate::serial::_print(format_args!("[\x1b[0;35mDBUG\x1b[0m] "));
-        $crate::serial::_print(format_args!($($arg)*));
-        $crate::serial::_print(format_args!("\n"));
+    ($fmt:expr $(, $($arg:tt)*)?) => ({
+        $crate::check_colors!($fmt);
+        if $crate::logger::enabled($crate::logger::Sink::Vga, $crate::logger::Level::Debug) {
+            $crate::vga_writer::_print(format_args!(
+                "[$05DBUG$!] {}\n",
+                format_args!($fmt $(, $($arg)*)?)
+            ));
+        }
+        if $crate::logger::enabled($crate::logger::Sink::Serial, $crate::logger::Level::Debug) {
+            $crate::serial::_print(format_args!(
+                "[\x1b[0;35mDBUG\x1b[0m] {}\n",
+                format_args!($fmt $(, $($arg)*)?)
+            ));
+        }
     });
 }
 
 #[cfg(feature = "qemu-connect")]
 #[macro_export]
 macro_rules! error {
-    ($($arg:tt)*) => ({
-        $crate::vga_writer::_print(format_args!("[$04ERRO$!] "));
-        $crate::vga_writer::_print(format_args!($($arg)*));
-        $crate::vga_writer::_print(format_args!("\n"));
-        $crate::serial::_print(format_args!("[\x1b[0;31mERRO\x1b[0m] "));
-        $crate::serial::_print(format_args!($($arg)*));
-        $crate::serial::_print(format_args!("\n"));
+    ($fmt:expr $(, $($arg:tt)*)?) => ({
+        $crate::check_colors!($fmt);
+        if $crate::logger::enabled($crate::logger::Sink::Vga, $crate::logger::Level::Error) {
+            $crate::vga_writer::_print(format_args!(
+                "[$04ERRO$!] {}\n",
+                format_args!($fmt $(, $($arg)*)?)
+            ));
+        }
+        if $crate::logger::enabled($crate::logger::Sink::Serial, $crate::logger::Level::Error) {
+            $crate::serial::_print(format_args!(
+                "[\x1b[0;31mERRO\x1b[0m] {}\n",
+                format_args!($fmt $(, $($arg)*)?)
+            ));
+        }
     });
 }
 
 #[cfg(feature = "qemu-connect")]
 #[macro_export]
 macro_rules! done {
-    ($($arg:tt)*) => ({
-        $crate::vga_writer::_print(format_args!("[$0ADONE$!] "));
-        $crate::vga_writer::_print(format_args!($($arg)*));
-        $crate::vga_writer::_print(format_args!("\n"));
-        $crate::serial::_print(format_args!("[\x1b[0;32mDONE\x1b[0m] "));
-        $crate::serial::_print(format_args!($($arg)*));
-        $crate::serial::_print(format_args!("\n"));
+    ($fmt:expr $(, $($arg:tt)*)?) => ({
+        $crate::check_colors!($fmt);
+        if $crate::logger::enabled($crate::logger::Sink::Vga, $crate::logger::Level::Info) {
+            $crate::vga_writer::_print(format_args!(
+                "[$0ADONE$!] {}\n",
+                format_args!($fmt $(, $($arg)*)?)
+            ));
+        }
+        if $crate::logger::enabled($crate::logger::Sink::Serial, $crate::logger::Level::Info) {
+            $crate::serial::_print(format_args!(
+                "[\x1b[0;32mDONE\x1b[0m] {}\n",
+                format_args!($fmt $(, $($arg)*)?)
+            ));
+        }
     });
 }
 #[cfg(feature = "qemu-connect")]
 #[macro_export]
 macro_rules! warn {
-    ($($arg:tt)*) => ({
-        $crate::vga_writer::_print(format_args!("[$0EWARN$!] "));
-        $crate::vga_writer::_print(format_args!($($arg)*));
-        $crate::vga_writer::_print(format_args!("\n"));
-        $crate::serial::_print(format_args!("[\x1b[0;33mWARN\x1b[0m] "));
-        $crate::serial::_print(format_args!($($arg)*));
-        $crate::serial::_print(format_args!("\n"));
+    ($fmt:expr $(, $($arg:tt)*)?) => ({
+        $crate::check_colors!($fmt);
+        if $crate::logger::enabled($crate::logger::Sink::Vga, $crate::logger::Level::Warn) {
+            $crate::vga_writer::_print(format_args!(
+                "[$0EWARN$!] {}\n",
+                format_args!($fmt $(, $($arg)*)?)
+            ));
+        }
+        if $crate::logger::enabled($crate::logger::Sink::Serial, $crate::logger::Level::Warn) {
+            $crate::serial::_print(format_args!(
+                "[\x1b[0;33mWARN\x1b[0m] {}\n",
+                format_args!($fmt $(, $($arg)*)?)
+            ));
+        }
     });
 }
 #[cfg(feature = "qemu-connect")]
 #[macro_export]
 macro_rules! info {
-    ($($arg:tt)*) => ({
-        $crate::vga_writer::_print(format_args!("[$03INFO$!] "));
-        $crate::vga_writer::_print(format_args!($($arg)*));
-        $crate::vga_writer::_print(format_args!("\n"));
-        $crate::serial::_print(format_args!("[\x1b[0;36mINFO\x1b[0m] "));
-        $crate::serial::_print(format_args!($($arg)*));
-        $crate::serial::_print(format_args!("\n"));
+    ($fmt:expr $(, $($arg:tt)*)?) => ({
+        $crate::check_colors!($fmt);
+        if $crate::logger::enabled($crate::logger::Sink::Vga, $crate::logger::Level::Info) {
+            $crate::vga_writer::_print(format_args!(
+                "[$03INFO$!] {}\n",
+                format_args!($fmt $(, $($arg)*)?)
+            ));
+        }
+        if $crate::logger::enabled($crate::logger::Sink::Serial, $crate::logger::Level::Info) {
+            $crate::serial::_print(format_args!(
+                "[\x1b[0;36mINFO\x1b[0m] {}\n",
+                format_args!($fmt $(, $($arg)*)?)
+            ));
+        }
     });
 }
+
+#[test_case]
+fn test_per_sink_levels() {
+    let vga_level = VGA_LOG_LEVEL.swap(Level::Error as usize, Ordering::Relaxed);
+    assert!(enabled(Sink::Vga, Level::Error));
+    assert!(!enabled(Sink::Vga, Level::Debug));
+    assert!(enabled(Sink::Serial, Level::Debug));
+    VGA_LOG_LEVEL.store(vga_level, Ordering::Relaxed);
+}
@@ -10,7 +10,7 @@ use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
 use core::panic::PanicInfo;
 use genos;
 use genos::allocator;
-use genos::{debug, done, error, info, println}; // new import
+use genos::{debug, done, error, info, println, warn}; // new import
 
 use bootloader::{entry_point, BootInfo};
 
@@ -29,6 +29,9 @@ pub fn entry_fct(boot_info: &'static BootInfo) -> ! {
     test_main();
 
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    if !unsafe { genos::memory::a20_enabled(phys_mem_offset) } {
+        warn!("A20 line is disabled, physical memory wraps around at 1MiB");
+    }
     let l4_table = unsafe { active_level_4_table(phys_mem_offset) };
 
     debug!("Listing pages entries :");
@@ -38,8 +41,20 @@ pub fn entry_fct(boot_info: &'static BootInfo) -> ! {
         }
     }
 
+    if !unsafe { genos::layout::report(phys_mem_offset) } {
+        warn!("Kernel image mapping isn't W^X, see the pages above");
+    }
+    unsafe {
+        genos::vm::protect_physical_memory(phys_mem_offset, &boot_info.memory_map);
+        genos::vm::audit(phys_mem_offset);
+    }
+
     let mut mapper = unsafe { genos::memory::init(phys_mem_offset) };
     let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    #[cfg(feature = "memtest")]
+    unsafe {
+        genos::memtest::run(&boot_info.memory_map, phys_mem_offset, &mut frame_allocator)
+    };
 
     let addresses = [
         // the identity-mapped vga buffer page
@@ -58,6 +73,9 @@ pub fn entry_fct(boot_info: &'static BootInfo) -> ! {
 
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
 
+    #[cfg(feature = "selftest")]
+    genos::selftest::run();
+
     // allocate a number on the heap
     let heap_value = Box::new(41);
     debug!("heap_value at {:p}", heap_value);
@@ -95,6 +113,7 @@ entry_point!(entry_fct);
 fn panic(info: &PanicInfo) -> ! {
     error!("PANIC");
     error!("{}", info);
+    genos::serial::flush();
     genos::hlt_loop();
 }
 
@@ -3,7 +3,13 @@ use x86_64::structures::paging::FrameAllocator;
 use x86_64::structures::paging::OffsetPageTable;
 use x86_64::structures::paging::PhysFrame;
 use x86_64::structures::paging::Size4KiB;
-use x86_64::{structures::paging::PageTable, PhysAddr, VirtAddr};
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::kbug;
+
+/// Heap usage and fragmentation, measured by the kernel heap in `allocator`.
+pub use crate::allocator::{stats, HeapStats};
+
 /// Initialize a new OffsetPageTable.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -74,7 +80,7 @@ fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Opt
         frame = match entry.frame() {
             Ok(frame) => frame,
             Err(FrameError::FrameNotPresent) => return None,
-            Err(FrameError::HugeFrame) => panic!("huge pages not supported"),
+            Err(FrameError::HugeFrame) => kbug!("huge pages not supported"),
         };
     }
 
@@ -82,10 +88,46 @@ fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Opt
     Some(frame.start_address() + u64::from(addr.page_offset()))
 }
 
+/// Checks that the A20 line is enabled, by looking for physical memory wrapping
+/// around at 1MiB.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `physical_memory_offset`. Both tested words are restored afterwards.
+pub unsafe fn a20_enabled(physical_memory_offset: VirtAddr) -> bool {
+    use x86_64::instructions::interrupts;
+
+    let low: *mut u32 = (physical_memory_offset + 0x0500u64).as_mut_ptr();
+    let high: *mut u32 = (physical_memory_offset + 0x10_0500u64).as_mut_ptr();
+    interrupts::without_interrupts(|| {
+        let saved_low = low.read_volatile();
+        let saved_high = high.read_volatile();
+        low.write_volatile(0x1234_5678);
+        high.write_volatile(!0x1234_5678);
+        let enabled = low.read_volatile() != high.read_volatile();
+        // high first : if they alias, `low` gets its own value back last
+        high.write_volatile(saved_high);
+        low.write_volatile(saved_low);
+        enabled
+    })
+}
+
+/// Frames that can be excluded from allocation, see `BootInfoFrameAllocator::exclude`.
+pub const MAX_BAD_FRAMES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExcludeError {
+    /// `MAX_BAD_FRAMES` frames are already excluded.
+    TooManyFrames,
+    /// Frames were already allocated, excluding one now would shift the others.
+    AllocationStarted,
+}
+
 /// A FrameAllocator that returns usable frames from the bootloader's memory map.
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
     next: usize,
+    bad_frames: [Option<PhysFrame>; MAX_BAD_FRAMES],
 }
 
 impl BootInfoFrameAllocator {
@@ -98,8 +140,24 @@ impl BootInfoFrameAllocator {
         BootInfoFrameAllocator {
             memory_map,
             next: 0,
+            bad_frames: [None; MAX_BAD_FRAMES],
         }
     }
+
+    /// Never hands out `frame`, for frames that failed a memory test. Must be
+    /// called before the first allocation.
+    pub fn exclude(&mut self, frame: PhysFrame) -> Result<(), ExcludeError> {
+        if self.next > 0 {
+            return Err(ExcludeError::AllocationStarted);
+        }
+        let slot = self
+            .bad_frames
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(ExcludeError::TooManyFrames)?;
+        *slot = Some(frame);
+        Ok(())
+    }
 }
 
 impl BootInfoFrameAllocator {
@@ -113,7 +171,11 @@ impl BootInfoFrameAllocator {
         // transform to an iterator of frame start addresses
         let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
         // create `PhysFrame` types from the start addresses
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+        let frames =
+            frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)));
+        // skip the ones that failed a memory test
+        let bad_frames = self.bad_frames;
+        frames.filter(move |frame| !bad_frames.contains(&Some(*frame)))
     }
 }
 
@@ -124,3 +186,67 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
         frame
     }
 }
+
+/// Returns the effective flags of the mapping containing `addr`, or `None` if
+/// the address is not mapped.
+///
+/// The flags of every level are combined like the MMU does : the page is only
+/// writable or user accessible if all levels allow it, and non executable if any
+/// level says so. `HUGE_PAGE` is set if the walk stopped on a 2MiB/1GiB page.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `physical_memory_offset`.
+pub unsafe fn translate_flags(
+    addr: VirtAddr,
+    physical_memory_offset: VirtAddr,
+) -> Option<PageTableFlags> {
+    translate_flags_inner(addr, physical_memory_offset)
+}
+
+/// Applies the flags of one more level of the walk to the effective `flags`.
+pub(crate) fn combine_flags(flags: PageTableFlags, entry: PageTableFlags) -> PageTableFlags {
+    (flags & (entry | PageTableFlags::NO_EXECUTE | PageTableFlags::HUGE_PAGE))
+        | (entry & PageTableFlags::NO_EXECUTE)
+}
+
+fn translate_flags_inner(
+    addr: VirtAddr,
+    physical_memory_offset: VirtAddr,
+) -> Option<PageTableFlags> {
+    use x86_64::registers::control::Cr3;
+
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let table_indexes = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let mut table_addr = level_4_table_frame.start_address();
+    let mut effective = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::USER_ACCESSIBLE;
+
+    for (level, &index) in table_indexes.iter().enumerate() {
+        let virt = physical_memory_offset + table_addr.as_u64();
+        let table_ptr: *const PageTable = virt.as_ptr();
+        let table = unsafe { &*table_ptr };
+
+        let entry = &table[index];
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        effective = combine_flags(effective, flags);
+
+        // the level 4 table can't hold huge pages, the level 1 bit means PAT
+        if level != 0 && level != 3 && flags.contains(PageTableFlags::HUGE_PAGE) {
+            return Some(effective | PageTableFlags::HUGE_PAGE);
+        }
+        table_addr = entry.addr();
+    }
+
+    Some(effective)
+}
@@ -0,0 +1,94 @@
+//! Boot time memory test, built with the `memtest` feature.
+//!
+//! Runs over every usable frame of the memory map before the frame allocator
+//! hands any out, through the physical memory mapping : each frame is filled
+//! and checked with walking ones, walking zeros and its own addresses. Frames
+//! that fail are logged and excluded from allocation. QEMU's memory is fine,
+//! this is for real machines with doubtful RAM.
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::structures::paging::PhysFrame;
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::memory::BootInfoFrameAllocator;
+use crate::{debug, done, warn};
+
+const WORDS_PER_FRAME: usize = 4096 / 8;
+
+#[derive(Debug, Clone, Copy)]
+enum Pattern {
+    WalkingOnes,
+    WalkingZeros,
+    OwnAddress,
+}
+
+const PATTERNS: [Pattern; 3] = [
+    Pattern::WalkingOnes,
+    Pattern::WalkingZeros,
+    Pattern::OwnAddress,
+];
+
+impl Pattern {
+    fn word(self, frame: PhysFrame, index: usize) -> u64 {
+        let bit = 1u64 << (index % 64);
+        match self {
+            Pattern::WalkingOnes => bit,
+            Pattern::WalkingZeros => !bit,
+            Pattern::OwnAddress => frame.start_address().as_u64() + index as u64 * 8,
+        }
+    }
+}
+
+/// Writes then checks every pattern on `frame`, returns the failing one.
+unsafe fn test_frame(frame: PhysFrame, physical_memory_offset: VirtAddr) -> Option<Pattern> {
+    let words = (physical_memory_offset + frame.start_address().as_u64()).as_mut_ptr::<u64>();
+    for &pattern in PATTERNS.iter() {
+        for index in 0..WORDS_PER_FRAME {
+            words.add(index).write_volatile(pattern.word(frame, index));
+        }
+        for index in 0..WORDS_PER_FRAME {
+            if words.add(index).read_volatile() != pattern.word(frame, index) {
+                return Some(pattern);
+            }
+        }
+    }
+    None
+}
+
+/// Tests the usable memory and excludes the bad frames from `frame_allocator`,
+/// which must not have allocated anything yet. Returns the number of bad frames.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped at `physical_memory_offset` and that
+/// the usable frames of `memory_map` are really unused : their content is lost.
+pub unsafe fn run(
+    memory_map: &MemoryMap,
+    physical_memory_offset: VirtAddr,
+    frame_allocator: &mut BootInfoFrameAllocator,
+) -> usize {
+    let mut tested = 0;
+    let mut bad = 0;
+    for region in memory_map.iter() {
+        if region.region_type != MemoryRegionType::Usable {
+            continue;
+        }
+        debug!(
+            "memtest: {:#x}..{:#x}",
+            region.range.start_addr(),
+            region.range.end_addr()
+        );
+        for address in (region.range.start_addr()..region.range.end_addr()).step_by(4096) {
+            let frame = PhysFrame::containing_address(PhysAddr::new(address));
+            tested += 1;
+            if let Some(pattern) = test_frame(frame, physical_memory_offset) {
+                bad += 1;
+                warn!("memtest: frame $0C{:#x}$! failed {:?}", address, pattern);
+                if let Err(error) = frame_allocator.exclude(frame) {
+                    warn!("memtest: can't exclude it ({:?})", error);
+                }
+            }
+        }
+    }
+    done!("memtest: {} frames tested, {} bad", tested, bad);
+    bad
+}
@@ -0,0 +1,45 @@
+//! Orderly reboot and poweroff.
+//!
+//! Every registered driver gets its shutdown hook first (serial output drains,
+//! the keyboard stops), then the machine is reset or switched off. There is no
+//! ACPI support yet, so poweroff uses the fixed ports of the emulators :
+//! on real hardware it logs that the machine can be switched off and halts.
+
+use spin::Mutex;
+
+use crate::io::ports::PowerOff;
+use crate::{driver, hlt_loop, info, ps2, warn};
+
+static POWER_OFF: Mutex<PowerOff> = Mutex::new(unsafe { PowerOff::new() });
+
+fn shutdown(what: &str) {
+    info!("{}: shutting down drivers", what);
+    driver::shutdown_all();
+    x86_64::instructions::interrupts::disable();
+}
+
+/// Makes the CPU triple fault, which resets it : no IDT, then an exception.
+fn triple_fault() {
+    use x86_64::instructions::tables::lidt;
+    use x86_64::structures::DescriptorTablePointer;
+
+    let empty = DescriptorTablePointer { limit: 0, base: 0 };
+    unsafe { lidt(&empty) };
+    x86_64::instructions::interrupts::int3();
+}
+
+pub fn reboot() -> ! {
+    shutdown("Reboot");
+    ps2::pulse_reset();
+    triple_fault();
+    hlt_loop()
+}
+
+pub fn poweroff() -> ! {
+    shutdown("Poweroff");
+    if let Some(mut power_off) = POWER_OFF.try_lock() {
+        power_off.power_off();
+    }
+    warn!("No poweroff method worked, it's now safe to turn off the machine");
+    hlt_loop()
+}
@@ -0,0 +1,98 @@
+//! Preemption control and atomic context detection.
+//!
+//! Code that must not be switched away from (holding a spinlock shared with a
+//! task, touching per-CPU state...) brackets itself with `preempt_disable` and
+//! `preempt_enable`, or holds a `PreemptGuard`. Together with the interrupt
+//! nesting level and the interrupt flag this tells whether we are allowed to
+//! block, which `might_sleep` checks in debug builds.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{interrupts, kbug};
+
+static PREEMPT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+pub fn preempt_disable() {
+    PREEMPT_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn preempt_enable() {
+    let previous = PREEMPT_COUNT.fetch_sub(1, Ordering::Relaxed);
+    if previous == 0 {
+        kbug!("preempt_enable without matching preempt_disable");
+    }
+}
+
+/// Number of nested `preempt_disable` currently in effect.
+pub fn preempt_count() -> usize {
+    PREEMPT_COUNT.load(Ordering::Relaxed)
+}
+
+/// Disables preemption until dropped.
+pub struct PreemptGuard(());
+
+impl Default for PreemptGuard {
+    fn default() -> PreemptGuard {
+        PreemptGuard::new()
+    }
+}
+
+impl PreemptGuard {
+    pub fn new() -> PreemptGuard {
+        preempt_disable();
+        PreemptGuard(())
+    }
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        preempt_enable();
+    }
+}
+
+/// True while running an interrupt or exception handler.
+pub fn in_interrupt() -> bool {
+    interrupts::nesting() > 0
+}
+
+/// True when blocking is not allowed : in an interrupt handler, with preemption
+/// disabled, or with interrupts disabled (nothing could wake us up).
+pub fn in_atomic() -> bool {
+    in_interrupt() || preempt_count() > 0 || !x86_64::instructions::interrupts::are_enabled()
+}
+
+/// To be called at the start of anything that may block. In debug builds it
+/// reports a kernel bug if called from atomic context.
+///
+/// Only `time::sleep_ms` calls it for now. The status polling loops of the
+/// PS/2 controller, the UART and the RTC spin without ever giving up the CPU
+/// and are legitimately used with interrupts disabled, so they do not.
+#[track_caller]
+pub fn might_sleep() {
+    if cfg!(debug_assertions) && in_atomic() {
+        kbug!(
+            "blocking call from atomic context at {} (interrupt depth {}, preempt count {}, interrupts {})",
+            core::panic::Location::caller(),
+            interrupts::nesting(),
+            preempt_count(),
+            if x86_64::instructions::interrupts::are_enabled() {
+                "enabled"
+            } else {
+                "disabled"
+            }
+        );
+    }
+}
+
+#[test_case]
+fn test_preempt_guard_nests() {
+    let before = preempt_count();
+    {
+        let _outer = PreemptGuard::new();
+        let _inner = PreemptGuard::new();
+        assert_eq!(preempt_count(), before + 2);
+        assert!(in_atomic());
+    }
+    assert_eq!(preempt_count(), before);
+    assert!(!in_interrupt());
+}
@@ -0,0 +1,246 @@
+//! 8042 PS/2 controller bring-up.
+//!
+//! QEMU hands over a controller that is already configured with an empty
+//! output buffer. Real machines may not : the BIOS can leave bytes pending, USB
+//! legacy emulation may have it in any state, and some machines have no 8042
+//! at all. `init` puts it in a known state before the keyboard IRQ is unmasked.
+
+use spin::{Mutex, MutexGuard};
+
+use crate::driver::{Driver, DriverError};
+use crate::interrupts::{self, InterruptIndex};
+use crate::io::ports::Ps2Controller;
+use crate::{debug, warn};
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_SECOND_PORT: u8 = 0xA7;
+const CMD_SELF_TEST: u8 = 0xAA;
+const CMD_TEST_FIRST_PORT: u8 = 0xAB;
+const CMD_DISABLE_FIRST_PORT: u8 = 0xAD;
+const CMD_ENABLE_FIRST_PORT: u8 = 0xAE;
+/// Pulses the CPU reset line low.
+const CMD_PULSE_RESET: u8 = 0xFE;
+
+const CONFIG_FIRST_PORT_IRQ: u8 = 1 << 0;
+const CONFIG_SECOND_PORT_IRQ: u8 = 1 << 1;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+/// Polls before giving up on the controller, roughly tens of milliseconds.
+const TIMEOUT: usize = 100_000;
+/// Bytes read before deciding the output buffer will never drain.
+const MAX_FLUSH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// The status register reads as a floating bus.
+    NoController,
+    /// The controller stopped responding.
+    Timeout,
+    /// The controller or the first port failed its self test.
+    SelfTestFailed(u8),
+}
+
+/// The only `Ps2Controller`, the keyboard handler reads it through `read_scancode`.
+static CONTROLLER: Mutex<Ps2Controller> = Mutex::new(unsafe { Ps2Controller::new() });
+
+/// The controller, taken for a command sequence.
+struct Controller(MutexGuard<'static, Ps2Controller>);
+
+impl Controller {
+    /// Should be called with interrupts disabled, or the keyboard bytes that
+    /// arrive meanwhile are lost.
+    fn lock() -> Controller {
+        Controller(CONTROLLER.lock())
+    }
+
+    fn status(&mut self) -> u8 {
+        self.0.status()
+    }
+
+    fn wait_input_empty(&mut self) -> Result<(), Ps2Error> {
+        for _ in 0..TIMEOUT {
+            if self.status() & STATUS_INPUT_FULL == 0 {
+                return Ok(());
+            }
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    fn wait_output_full(&mut self) -> Result<(), Ps2Error> {
+        for _ in 0..TIMEOUT {
+            if self.status() & STATUS_OUTPUT_FULL != 0 {
+                return Ok(());
+            }
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    fn command(&mut self, command: u8) -> Result<(), Ps2Error> {
+        self.wait_input_empty()?;
+        // only the commands of this module, the reset one included on purpose
+        unsafe { self.0.write_command(command) };
+        Ok(())
+    }
+
+    fn write_data(&mut self, value: u8) -> Result<(), Ps2Error> {
+        self.wait_input_empty()?;
+        // only ever the configuration byte
+        unsafe { self.0.write_data(value) };
+        Ok(())
+    }
+
+    fn read_data(&mut self) -> Result<u8, Ps2Error> {
+        self.wait_output_full()?;
+        Ok(self.0.read_data())
+    }
+
+    /// Drops whatever the BIOS or a previous keypress left in the output buffer.
+    fn flush(&mut self) -> Result<(), Ps2Error> {
+        for _ in 0..MAX_FLUSH {
+            if self.status() & STATUS_OUTPUT_FULL == 0 {
+                return Ok(());
+            }
+            self.0.read_data();
+        }
+        Err(Ps2Error::Timeout)
+    }
+}
+
+/// Returns false if reading the status register looks like there is no 8042.
+pub fn controller_present() -> bool {
+    Controller::lock().status() != 0xFF
+}
+
+/// Reads the byte the keyboard sent. Called by the IRQ1 handler, `None` if the
+/// controller is in the middle of a command sequence.
+pub(crate) fn read_scancode() -> Option<u8> {
+    CONTROLLER
+        .try_lock()
+        .map(|mut controller| controller.read_data())
+}
+
+/// Initialises the controller with the keyboard on the first port, scancode
+/// translation (set 1) and its IRQ enabled, the second port left disabled.
+///
+/// Must run with interrupts disabled : the IRQ1 handler would steal the
+/// controller's replies from port 0x60.
+pub fn init() -> Result<(), Ps2Error> {
+    debug!("Initialisation of the PS/2 controller");
+    if !controller_present() {
+        return Err(Ps2Error::NoController);
+    }
+    let mut controller = Controller::lock();
+
+    controller.command(CMD_DISABLE_FIRST_PORT)?;
+    controller.command(CMD_DISABLE_SECOND_PORT)?;
+    controller.flush()?;
+
+    controller.command(CMD_READ_CONFIG)?;
+    let mut config = controller.read_data()?;
+    config &= !(CONFIG_FIRST_PORT_IRQ | CONFIG_SECOND_PORT_IRQ);
+    config |= CONFIG_TRANSLATION;
+    controller.command(CMD_WRITE_CONFIG)?;
+    controller.write_data(config)?;
+
+    controller.command(CMD_SELF_TEST)?;
+    let result = controller.read_data()?;
+    if result != SELF_TEST_PASSED {
+        return Err(Ps2Error::SelfTestFailed(result));
+    }
+    // some controllers reset their configuration during the self test
+    controller.command(CMD_WRITE_CONFIG)?;
+    controller.write_data(config)?;
+
+    controller.command(CMD_TEST_FIRST_PORT)?;
+    let result = controller.read_data()?;
+    if result != PORT_TEST_PASSED {
+        return Err(Ps2Error::SelfTestFailed(result));
+    }
+
+    controller.command(CMD_ENABLE_FIRST_PORT)?;
+    controller.command(CMD_WRITE_CONFIG)?;
+    controller.write_data(config | CONFIG_FIRST_PORT_IRQ)?;
+    controller.flush()?;
+    Ok(())
+}
+
+/// Resets the machine through the controller's reset line. Returns if the
+/// controller is missing or the reset didn't happen.
+pub(crate) fn pulse_reset() {
+    if !controller_present() {
+        return;
+    }
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut controller = Controller::lock();
+        if controller.command(CMD_PULSE_RESET).is_ok() {
+            // the reset takes a moment to assert
+            for _ in 0..TIMEOUT {
+                core::sync::atomic::spin_loop_hint();
+            }
+        }
+    });
+}
+
+/// Like `init`, logging instead of failing. Returns true if the keyboard is usable.
+pub fn init_or_warn() -> bool {
+    match init() {
+        Ok(()) => true,
+        Err(error) => {
+            warn!("PS/2 controller unusable ({:?}), keyboard disabled", error);
+            false
+        }
+    }
+}
+
+impl From<Ps2Error> for DriverError {
+    fn from(error: Ps2Error) -> DriverError {
+        match error {
+            Ps2Error::Timeout => DriverError::Timeout,
+            Ps2Error::NoController | Ps2Error::SelfTestFailed(_) => DriverError::Failed,
+        }
+    }
+}
+
+/// Power management of the keyboard on the first port.
+pub struct KeyboardDriver;
+
+pub static KEYBOARD_DRIVER: KeyboardDriver = KeyboardDriver;
+
+impl Driver for KeyboardDriver {
+    fn name(&self) -> &'static str {
+        "ps2-keyboard"
+    }
+
+    fn suspend(&self) -> Result<(), DriverError> {
+        x86_64::instructions::interrupts::without_interrupts(|| -> Result<(), DriverError> {
+            interrupts::mask_irq(InterruptIndex::Keyboard.irq());
+            let mut controller = Controller::lock();
+            controller.command(CMD_DISABLE_FIRST_PORT)?;
+            controller.flush()?;
+            Ok(())
+        })
+    }
+
+    fn resume(&self) -> Result<(), DriverError> {
+        x86_64::instructions::interrupts::without_interrupts(|| -> Result<(), DriverError> {
+            let mut controller = Controller::lock();
+            controller.flush()?;
+            controller.command(CMD_ENABLE_FIRST_PORT)?;
+            interrupts::unmask_irq(InterruptIndex::Keyboard.irq());
+            Ok(())
+        })
+    }
+
+    fn shutdown(&self) {
+        if let Err(error) = self.suspend() {
+            warn!("PS/2 keyboard didn't stop: {:?}", error);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Scripted keyboard input, for regression testing what reads the keyboard.
+//!
+//! A script is played back from the timer interrupt, feeding scancodes through
+//! the same decoder as the real keyboard. One command per line (or separated
+//! by `;`), `#` starts a comment :
+//!
+//! ```text
+//! # type "az" then wait a second
+//! sc 10 90 11 91
+//! wait 1000
+//! ```
+//!
+//! * `sc <hex>...` injects the given set 1 scancodes, releases included
+//! * `wait <ms>` pauses the playback
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::{keyboard, time};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptError {
+    /// The line number (from 1) doesn't start with a known command.
+    UnknownCommand(usize),
+    /// The line number (from 1) has a missing or malformed argument.
+    BadArgument(usize),
+    /// A script is already playing.
+    Busy,
+}
+
+enum Command<'a> {
+    Scancodes(core::str::SplitWhitespace<'a>),
+    Wait(u64),
+}
+
+fn parse_line(line: &str, number: usize) -> Result<Option<Command>, ScriptError> {
+    let line = match line.find('#') {
+        Some(comment) => &line[..comment],
+        None => line,
+    };
+    let mut words = line.split_whitespace();
+    match words.next() {
+        None => Ok(None),
+        Some("sc") => {
+            let scancodes = words.clone();
+            if words.clone().next().is_none()
+                || words.any(|word| u8::from_str_radix(word, 16).is_err())
+            {
+                return Err(ScriptError::BadArgument(number));
+            }
+            Ok(Some(Command::Scancodes(scancodes)))
+        }
+        Some("wait") => {
+            let ms = words
+                .next()
+                .and_then(|word| word.parse().ok())
+                .ok_or(ScriptError::BadArgument(number))?;
+            if words.next().is_some() {
+                return Err(ScriptError::BadArgument(number));
+            }
+            Ok(Some(Command::Wait(ms)))
+        }
+        Some(_) => Err(ScriptError::UnknownCommand(number)),
+    }
+}
+
+fn lines(script: &str) -> impl Iterator<Item = &str> {
+    script.split(|c: char| c == '\n' || c == ';')
+}
+
+/// Checks a script without playing it.
+pub fn validate(script: &str) -> Result<(), ScriptError> {
+    for (index, line) in lines(script).enumerate() {
+        parse_line(line, index + 1)?;
+    }
+    Ok(())
+}
+
+struct Player {
+    script: Option<&'static str>,
+    /// Index of the next line to run.
+    line: usize,
+    /// Tick count before which nothing runs.
+    resume_at: u64,
+}
+
+static PLAYER: Mutex<Player> = Mutex::new(Player {
+    script: None,
+    line: 0,
+    resume_at: 0,
+});
+
+/// Starts playing `script` from the next timer tick.
+pub fn start(script: &'static str) -> Result<(), ScriptError> {
+    validate(script)?;
+    interrupts::without_interrupts(|| {
+        let mut player = PLAYER.lock();
+        if player.script.is_some() {
+            return Err(ScriptError::Busy);
+        }
+        player.script = Some(script);
+        player.line = 0;
+        player.resume_at = 0;
+        Ok(())
+    })
+}
+
+/// Stops the playback, if any.
+pub fn stop() {
+    interrupts::without_interrupts(|| PLAYER.lock().script = None);
+}
+
+pub fn is_playing() -> bool {
+    interrupts::without_interrupts(|| PLAYER.lock().script.is_some())
+}
+
+/// Called by the timer interrupt handler : runs lines until a wait or the end.
+pub(crate) fn tick() {
+    let mut player = match PLAYER.try_lock() {
+        Some(player) => player,
+        None => return,
+    };
+    let script = match player.script {
+        Some(script) => script,
+        None => return,
+    };
+    let now = time::ticks();
+    if now < player.resume_at {
+        return;
+    }
+
+    let mut remaining = lines(script).enumerate().skip(player.line);
+    loop {
+        let (index, line) = match remaining.next() {
+            Some(next) => next,
+            None => {
+                player.script = None;
+                return;
+            }
+        };
+        player.line = index + 1;
+        // validated by `start`
+        match parse_line(line, index + 1) {
+            Ok(Some(Command::Scancodes(scancodes))) => {
+                for word in scancodes {
+                    if let Ok(scancode) = u8::from_str_radix(word, 16) {
+                        keyboard::add_scancode(scancode);
+                    }
+                }
+            }
+            Ok(Some(Command::Wait(ms))) => {
+                player.resume_at = now + time::ms_to_ticks(ms);
+                return;
+            }
+            Ok(None) | Err(_) => {}
+        }
+    }
+}
+
+#[test_case]
+fn test_script_validation() {
+    assert_eq!(validate("# nothing\n\nsc 10 90; wait 5"), Ok(()));
+    assert_eq!(validate("sc 10\ntype a"), Err(ScriptError::UnknownCommand(2)));
+    assert_eq!(validate("sc"), Err(ScriptError::BadArgument(1)));
+    assert_eq!(validate("sc 1g"), Err(ScriptError::BadArgument(1)));
+    assert_eq!(validate("wait soon"), Err(ScriptError::BadArgument(1)));
+}
+
+#[test_case]
+fn test_script_playback() {
+    while keyboard::read_char().is_some() {}
+    start("sc 10 90\nwait 1\nsc 11 91").expect("replay failed to start");
+    while is_playing() {
+        x86_64::instructions::hlt();
+    }
+    assert_eq!(keyboard::read_char(), Some('a'));
+    assert_eq!(keyboard::read_char(), Some('z'));
+}
@@ -0,0 +1,202 @@
+//! CMOS real time clock.
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::io::ports::Cmos;
+
+static CMOS: Mutex<Cmos> = Mutex::new(unsafe { Cmos::new() });
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+const STATUS_B_24_HOURS: u8 = 0x02;
+const STATUS_B_BINARY: u8 = 0x04;
+const HOURS_PM: u8 = 0x80;
+
+fn read_register(register: u8) -> u8 {
+    // the timer handler reads the seconds too
+    interrupts::without_interrupts(|| CMOS.lock().read(register))
+}
+
+/// True while the RTC updates its registers, reads may then be inconsistent.
+fn update_in_progress() -> bool {
+    read_register(REG_STATUS_A) & 0x80 != 0
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+fn is_binary() -> bool {
+    read_register(REG_STATUS_B) & STATUS_B_BINARY != 0
+}
+
+fn decode(value: u8) -> u8 {
+    if is_binary() {
+        value
+    } else {
+        from_bcd(value)
+    }
+}
+
+/// Current seconds (0-59) of the RTC.
+pub fn seconds() -> u8 {
+    while update_in_progress() {}
+    decode(read_register(REG_SECONDS))
+}
+
+/// Current seconds of the RTC, or `None` if it is updating right now.
+///
+/// Doesn't wait, so it can be used from interrupt handlers.
+pub fn try_seconds() -> Option<u8> {
+    if update_in_progress() {
+        None
+    } else {
+        Some(decode(read_register(REG_SECONDS)))
+    }
+}
+
+/// A date and time as kept by the RTC, which is assumed to run on UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01 00:00:00 UTC.
+    pub fn to_unix(&self) -> u64 {
+        days_from_civil(self.year as i64, self.month as i64, self.day as i64) as u64 * 86400
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+
+    /// The UTC date and time `seconds` after 1970-01-01 00:00:00.
+    pub fn from_unix(seconds: u64) -> DateTime {
+        let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+        let time = seconds % 86400;
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+}
+
+/// Days between 1970-01-01 and the given date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Howard Hinnant's algorithm, with years starting in March
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Inverse of `days_from_civil` : (year, month, day) of a day since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = (if days >= 0 { days } else { days - 146096 }) / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn read_raw() -> [u8; 6] {
+    while update_in_progress() {}
+    [
+        read_register(REG_SECONDS),
+        read_register(REG_MINUTES),
+        read_register(REG_HOURS),
+        read_register(REG_DAY),
+        read_register(REG_MONTH),
+        read_register(REG_YEAR),
+    ]
+}
+
+/// Reads the current date and time.
+pub fn now() -> DateTime {
+    // an update may still start between two reads, so read until stable
+    let mut raw = read_raw();
+    loop {
+        let again = read_raw();
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+
+    let status_b = read_register(REG_STATUS_B);
+    let [second, minute, hours, day, month, year] = raw;
+    let pm = hours & HOURS_PM != 0;
+    let mut hour = decode(hours & !HOURS_PM);
+    if status_b & STATUS_B_24_HOURS == 0 {
+        // 12 hour mode : 12 AM is midnight, 12 PM is noon
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+
+    DateTime {
+        // no reliable century register, assume we're past 2000
+        year: 2000 + decode(year) as u16,
+        month: decode(month),
+        day: decode(day),
+        hour,
+        minute: decode(minute),
+        second: decode(second),
+    }
+}
+
+#[test_case]
+fn test_unix_epoch_conversion() {
+    let epoch = DateTime {
+        year: 1970,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+    assert_eq!(epoch.to_unix(), 0);
+
+    let date = DateTime {
+        year: 2020,
+        month: 12,
+        day: 23,
+        hour: 13,
+        minute: 37,
+        second: 42,
+    };
+    assert_eq!(date.to_unix(), 1_608_730_662);
+    assert_eq!(DateTime::from_unix(1_608_730_662), date);
+    assert_eq!(DateTime::from_unix(0), epoch);
+}
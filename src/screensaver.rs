@@ -0,0 +1,82 @@
+//! Console blanking after a period without input.
+//!
+//! The display is switched off with the VGA sequencer's "screen off" bit, so
+//! the text buffer and anything printed meanwhile stay intact and just become
+//! visible again on the next key press. The hardware cursor is hidden too.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::io::ports::VgaSequencer;
+use crate::{time, vga_writer};
+
+const SEQUENCER_CLOCKING_MODE: u8 = 0x01;
+const CLOCKING_MODE_SCREEN_OFF: u8 = 1 << 5;
+
+const CRTC_CURSOR_START: u8 = 0x0A;
+const CURSOR_DISABLE: u8 = 1 << 5;
+
+/// Seconds without input before blanking, 0 never blanks (`console.blank_seconds`).
+pub static BLANK_SECONDS: AtomicUsize = AtomicUsize::new(10 * 60);
+
+static LAST_INPUT: AtomicU64 = AtomicU64::new(0);
+static BLANKED: AtomicBool = AtomicBool::new(false);
+
+static SEQUENCER: Mutex<VgaSequencer> = Mutex::new(unsafe { VgaSequencer::new() });
+
+fn update_bit(value: u8, set: bool, bit: u8) -> u8 {
+    if set {
+        value | bit
+    } else {
+        value & !bit
+    }
+}
+
+fn set_display(on: bool) {
+    interrupts::without_interrupts(|| {
+        let mut sequencer = SEQUENCER.lock();
+        let mut crtc = vga_writer::CRTC.lock();
+        let clocking_mode = sequencer.read(SEQUENCER_CLOCKING_MODE);
+        let cursor_start = crtc.read(CRTC_CURSOR_START);
+        // neither bit changes the frame buffer
+        unsafe {
+            sequencer.write(
+                SEQUENCER_CLOCKING_MODE,
+                update_bit(clocking_mode, !on, CLOCKING_MODE_SCREEN_OFF),
+            );
+            crtc.write(CRTC_CURSOR_START, update_bit(cursor_start, !on, CURSOR_DISABLE));
+        }
+    });
+}
+
+/// Blanks the console now.
+pub fn blank() {
+    if !BLANKED.swap(true, Ordering::Relaxed) {
+        set_display(false);
+    }
+}
+
+/// Restarts the idle countdown and unblanks the console. Called on input.
+pub fn wake() {
+    LAST_INPUT.store(time::ticks(), Ordering::Relaxed);
+    if BLANKED.swap(false, Ordering::Relaxed) {
+        set_display(true);
+    }
+}
+
+pub fn is_blanked() -> bool {
+    BLANKED.load(Ordering::Relaxed)
+}
+
+/// Called by the timer interrupt handler.
+pub(crate) fn tick() {
+    let seconds = BLANK_SECONDS.load(Ordering::Relaxed) as u64;
+    if seconds == 0 || is_blanked() {
+        return;
+    }
+    let idle = time::ticks() - LAST_INPUT.load(Ordering::Relaxed);
+    if idle >= time::ms_to_ticks(seconds * 1000) {
+        blank();
+    }
+}
@@ -0,0 +1,210 @@
+//! Power-on self tests, built with the `selftest` feature.
+//!
+//! Unlike the `cargo test` suites these run in the normal kernel, so they are
+//! meant for real hardware where QEMU's forgiving behaviour can't be assumed.
+//! Each test reports on the logger (and so over serial) and the boot goes on
+//! whatever the result.
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::interrupts::Exception;
+use crate::{allocator, ps2, rtc, time};
+use crate::{done, error, info};
+
+struct SelfTest {
+    name: &'static str,
+    run: fn() -> Result<(), &'static str>,
+}
+
+const TESTS: &[SelfTest] = &[
+    SelfTest {
+        name: "heap stress",
+        run: heap_stress,
+    },
+    SelfTest {
+        name: "idt round-trip",
+        run: idt_round_trip,
+    },
+    SelfTest {
+        name: "timer frequency",
+        run: timer_frequency,
+    },
+    SelfTest {
+        name: "interrupt latency",
+        run: interrupt_latency,
+    },
+    SelfTest {
+        name: "keyboard controller",
+        run: keyboard_controller,
+    },
+];
+
+/// Runs every self test, the heap must be initialised. Returns true if all passed.
+pub fn run() -> bool {
+    info!("Running {} self tests", TESTS.len());
+    let mut failed = 0;
+    for test in TESTS {
+        match (test.run)() {
+            Ok(()) => done!("selftest {}: pass", test.name),
+            Err(reason) => {
+                error!("selftest {}: $0CFAIL$! ({})", test.name, reason);
+                failed += 1;
+            }
+        }
+    }
+    if failed == 0 {
+        done!("All self tests passed");
+    } else {
+        error!("{} self test(s) failed", failed);
+    }
+    failed == 0
+}
+
+/// Allocates and frees blocks of mixed sizes out of order, checking contents.
+fn heap_stress() -> Result<(), &'static str> {
+    let mut blocks: Vec<Box<[u8]>> = Vec::new();
+    for round in 0..64usize {
+        let size = 1 + (round * 97) % 2048;
+        blocks.push(alloc::vec![round as u8; size].into_boxed_slice());
+        if round % 3 == 0 {
+            // free from the middle to fragment the free list
+            let block = blocks.remove(blocks.len() / 2);
+            if block.iter().any(|&b| b != block[0]) {
+                return Err("block content corrupted");
+            }
+        }
+    }
+    if blocks.iter().any(|block| block.iter().any(|&b| b != block[0])) {
+        return Err("block content corrupted");
+    }
+    drop(blocks);
+
+    // a single block of most of the heap must fit again once everything is freed
+    let big = alloc::vec![0xA5u8; allocator::HEAP_SIZE / 2];
+    if big.iter().any(|&b| b != 0xA5) {
+        return Err("large block content corrupted");
+    }
+    Ok(())
+}
+
+/// Checks that `int3` reaches our breakpoint handler and returns.
+fn idt_round_trip() -> Result<(), &'static str> {
+    let before = Exception::Breakpoint.count();
+    x86_64::instructions::interrupts::int3();
+    if Exception::Breakpoint.count() == before + 1 {
+        Ok(())
+    } else {
+        Err("breakpoint handler did not run")
+    }
+}
+
+/// Waits for the RTC seconds to change, giving up after a few million reads.
+fn wait_rtc_second() -> Result<(), &'static str> {
+    let start = rtc::seconds();
+    for _ in 0..5_000_000 {
+        if rtc::seconds() != start {
+            return Ok(());
+        }
+    }
+    Err("RTC is not ticking")
+}
+
+/// Counts timer ticks during one RTC second.
+fn timer_frequency() -> Result<(), &'static str> {
+    wait_rtc_second()?;
+    let start = time::ticks();
+    wait_rtc_second()?;
+    let ticks = time::ticks() - start;
+
+    let expected = time::HZ as u64;
+    info!("{} timer ticks in one RTC second, expected {}", ticks, expected);
+    if ticks == 0 {
+        Err("timer interrupt is not firing")
+    } else if ticks < expected * 3 / 4 || ticks > expected * 5 / 4 {
+        Err("timer frequency is off by more than 25%")
+    } else {
+        Ok(())
+    }
+}
+
+const LATENCY_ITERATIONS: u64 = 2000;
+/// One-shot delay, about 1ms.
+const LATENCY_PIT_COUNT: u16 = 1193;
+/// Upper bounds of the latency histogram buckets, in ns.
+const LATENCY_BUCKETS: [u64; 5] = [1_000, 5_000, 10_000, 50_000, 100_000];
+
+/// Measures the delay between the PIT firing a one-shot interrupt and the timer
+/// handler starting, using the TSC calibrated by the previous test.
+fn interrupt_latency() -> Result<(), &'static str> {
+    use x86_64::instructions::interrupts as cpu_interrupts;
+
+    // the first RTC edge is thrown away, so this takes a second more than
+    // `timer_frequency` waited
+    for _ in 0..time::MIN_CALIBRATION_SECONDS + 2 {
+        if time::drift_stats().tsc_hz.is_some() {
+            break;
+        }
+        wait_rtc_second()?;
+    }
+    let tsc_hz = time::drift_stats().tsc_hz.ok_or("TSC not calibrated")?;
+    let expected = LATENCY_PIT_COUNT as u64 * tsc_hz / time::PIT_FREQUENCY as u64;
+    let to_ns = |cycles: u64| cycles * 1_000_000_000 / tsc_hz;
+
+    let (mut min, mut max, mut total) = (u64::MAX, 0, 0);
+    let mut histogram = [0u64; LATENCY_BUCKETS.len() + 1];
+    let mut result = Ok(());
+    for _ in 0..LATENCY_ITERATIONS {
+        let start = cpu_interrupts::without_interrupts(|| {
+            time::pit_one_shot(LATENCY_PIT_COUNT);
+            (time::rdtsc(), time::ticks())
+        });
+        let (start_tsc, start_ticks) = start;
+
+        let deadline = start_tsc + 10 * expected;
+        let mut fired = false;
+        while time::rdtsc() < deadline {
+            if time::ticks() != start_ticks {
+                fired = true;
+                break;
+            }
+        }
+        if !fired {
+            result = Err("one-shot timer interrupt never came");
+            break;
+        }
+
+        let latency = to_ns((time::last_tick_tsc() - start_tsc).saturating_sub(expected));
+        min = min.min(latency);
+        max = max.max(latency);
+        total += latency;
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| latency < bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        histogram[bucket] += 1;
+    }
+    time::pit_periodic();
+    time::reset_calibration();
+    result?;
+
+    info!(
+        "interrupt latency over {} runs: min {} ns, avg {} ns, max {} ns",
+        LATENCY_ITERATIONS,
+        min,
+        total / LATENCY_ITERATIONS,
+        max
+    );
+    info!(
+        "  <1us: {}, <5us: {}, <10us: {}, <50us: {}, <100us: {}, more: {}",
+        histogram[0], histogram[1], histogram[2], histogram[3], histogram[4], histogram[5]
+    );
+    Ok(())
+}
+
+fn keyboard_controller() -> Result<(), &'static str> {
+    if !ps2::controller_present() {
+        Err("no 8042 controller")
+    } else {
+        Ok(())
+    }
+}
@@ -1,25 +1,184 @@
 
-use uart_16550::SerialPort;
+//! COM1, the link to the host.
+//!
+//! Output is queued in a ring and sent by the IRQ4 handler each time the UART
+//! has room, so printing doesn't wait on the line at 38400 baud. Until
+//! `enable_interrupt_tx` is called in stage 1, and whenever the ring is full,
+//! bytes are sent synchronously instead. `flush` pushes out everything queued,
+//! for panic paths and before leaving QEMU.
+//!
+//! The UART belongs to the ring : every access to it goes through `TX`.
+
+use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
-use lazy_static::lazy_static;
+use x86_64::instructions::interrupts;
+
+use crate::driver::{Driver, DriverError};
+use crate::io::ports::{DebugExit, Uart};
 
 const QEMU_PORT :u16 = 0x3F8;
+const INTERRUPT_DATA_AVAILABLE: u8 = 1 << 0;
+const INTERRUPT_TX_EMPTY: u8 = 1 << 1;
+const LINE_STATUS_DATA_READY: u8 = 1 << 0;
+/// Transmit FIFO empty.
+const LINE_STATUS_TX_EMPTY: u8 = 1 << 5;
+/// Transmit holding and shift registers both empty.
+const LINE_STATUS_TX_IDLE: u8 = 1 << 6;
+const DRAIN_TIMEOUT: usize = 100_000;
 
-lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(QEMU_PORT) };
-        serial_port.init();
-        Mutex::new(serial_port)
-    };
+pub const TX_CAPACITY: usize = 4096;
+
+struct TxRing {
+    uart: Uart,
+    /// Bytes the transmit FIFO takes once empty, 0 until the UART is set up.
+    fifo_size: usize,
+    bytes: [u8; TX_CAPACITY],
+    start: usize,
+    len: usize,
+}
+
+impl TxRing {
+    /// Sets the UART up on first use.
+    fn init(&mut self) {
+        if self.fifo_size == 0 {
+            self.fifo_size = self.uart.init();
+            self.set_tx_interrupt(false);
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.bytes[self.start];
+        self.start = (self.start + 1) % TX_CAPACITY;
+        self.len -= 1;
+        Some(byte)
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len == TX_CAPACITY {
+            // no room : send the oldest byte by hand
+            if let Some(oldest) = self.pop() {
+                self.send_blocking(oldest);
+            }
+        }
+        let end = (self.start + self.len) % TX_CAPACITY;
+        self.bytes[end] = byte;
+        self.len += 1;
+    }
+
+    fn send_blocking(&mut self, byte: u8) {
+        for _ in 0..DRAIN_TIMEOUT {
+            if self.uart.line_status() & LINE_STATUS_TX_EMPTY != 0 {
+                break;
+            }
+        }
+        self.uart.write_data(byte);
+    }
+
+    /// Sends everything queued, waiting on the UART.
+    fn send_all(&mut self) {
+        while let Some(byte) = self.pop() {
+            self.send_blocking(byte);
+        }
+    }
+
+    /// Fills the transmit FIFO if it is empty.
+    fn refill(&mut self) {
+        if self.uart.line_status() & LINE_STATUS_TX_EMPTY == 0 {
+            return;
+        }
+        for _ in 0..self.fifo_size {
+            match self.pop() {
+                Some(byte) => self.uart.write_data(byte),
+                None => break,
+            }
+        }
+    }
+
+    fn set_tx_interrupt(&mut self, enabled: bool) {
+        self.uart.set_interrupts(if enabled {
+            INTERRUPT_DATA_AVAILABLE | INTERRUPT_TX_EMPTY
+        } else {
+            INTERRUPT_DATA_AVAILABLE
+        });
+    }
+}
+
+impl core::fmt::Write for TxRing {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            self.push(byte);
+        }
+        Ok(())
+    }
 }
 
+static TX: Mutex<TxRing> = Mutex::new(TxRing {
+    // the only user of COM1
+    uart: unsafe { Uart::new(QEMU_PORT) },
+    fifo_size: 0,
+    bytes: [0; TX_CAPACITY],
+    start: 0,
+    len: 0,
+});
+static INTERRUPT_TX: AtomicBool = AtomicBool::new(false);
 
+/// Switches output to the IRQ4 driven queue. IRQ4 must be unmasked.
+pub fn enable_interrupt_tx() {
+    interrupts::without_interrupts(|| TX.lock().init());
+    INTERRUPT_TX.store(true, Ordering::Relaxed);
+}
+
+/// Called by the IRQ4 handler.
+pub(crate) fn interrupt() {
+    let mut tx = match TX.try_lock() {
+        Some(tx) => tx,
+        None => return,
+    };
+    // received bytes are not used, drop them so the interrupt clears
+    while tx.uart.line_status() & LINE_STATUS_DATA_READY != 0 {
+        tx.uart.read_data();
+    }
+    tx.refill();
+    if tx.len == 0 {
+        tx.set_tx_interrupt(false);
+    }
+}
 
+/// Sends everything queued, waiting on the UART.
+pub fn flush() {
+    interrupts::without_interrupts(|| {
+        // a panic while printing holds the lock, keep what is queued then
+        if let Some(mut tx) = TX.try_lock() {
+            tx.init();
+            tx.send_all();
+        }
+    });
+}
+
+/// Serial has no colors : the `$` escapes are stripped.
 #[doc(hidden)]
 #[cfg(feature = "qemu-connect")]
 pub fn _print(args: core::fmt::Arguments) {
     use core::fmt::Write;
-    SERIAL1.lock().write_fmt(args).expect("Printing to serial failed");
+
+    interrupts::without_interrupts(|| {
+        let mut tx = TX.lock();
+        tx.init();
+        crate::color::Strip(&mut *tx)
+            .write_fmt(args)
+            .expect("Printing to serial failed");
+        if INTERRUPT_TX.load(Ordering::Relaxed) {
+            tx.refill();
+            if tx.len > 0 {
+                tx.set_tx_interrupt(true);
+            }
+        } else {
+            tx.send_all();
+        }
+    });
 }
 
 /// Prints to the host through the serial interface.
@@ -61,6 +220,40 @@ macro_rules! qemu_debug {
 }
 
 
+/// Sends what is queued and waits until the last byte has left the UART.
+fn drain() -> Result<(), DriverError> {
+    interrupts::without_interrupts(|| {
+        let mut tx = TX.try_lock().ok_or(DriverError::Failed)?;
+        tx.init();
+        tx.send_all();
+        for _ in 0..DRAIN_TIMEOUT {
+            if tx.uart.line_status() & LINE_STATUS_TX_IDLE != 0 {
+                return Ok(());
+            }
+        }
+        Err(DriverError::Timeout)
+    })
+}
+
+/// Power management of the serial port : output in flight is let out first.
+pub struct SerialDriver;
+
+pub static SERIAL_DRIVER: SerialDriver = SerialDriver;
+
+impl Driver for SerialDriver {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+
+    fn suspend(&self) -> Result<(), DriverError> {
+        drain()
+    }
+
+    fn shutdown(&self) {
+        let _ = drain();
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum QemuExitCode {
@@ -68,11 +261,19 @@ pub enum QemuExitCode {
     Failed = 0x11,
 }
 
+/// The runner in Cargo.toml starts QEMU with `isa-debug-exit` at 0xF4.
+static DEBUG_EXIT: Mutex<DebugExit> = Mutex::new(unsafe { DebugExit::new() });
+
 pub fn exit_qemu(exit_code: QemuExitCode) {
-    use x86_64::instructions::port::Port;
+    let _ = drain();
+    DEBUG_EXIT.lock().exit(exit_code as u32);
+}
 
-    unsafe {
-        let mut port = Port::new(0xf4);
-        port.write(exit_code as u32);
+#[test_case]
+fn test_queued_output_drains() {
+    for _ in 0..TX_CAPACITY / 32 + 8 {
+        qemu_println!("{:>31}", "queued");
     }
+    flush();
+    assert_eq!(interrupts::without_interrupts(|| TX.lock().len), 0);
 }
@@ -0,0 +1,180 @@
+//! Pool of kernel stacks, each one preceded by an unmapped guard page so an
+//! overflow page faults instead of silently corrupting its neighbour.
+//!
+//! Stacks are carved out of a dedicated virtual region, one fixed size slot
+//! each. A freed stack keeps its mapping and goes to a free list, so recycling
+//! it doesn't touch the page tables or the frame allocator.
+
+use spin::Mutex;
+use x86_64::{
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+    },
+    VirtAddr,
+};
+
+use crate::{cover, kassert};
+
+pub const STACK_REGION_START: usize = 0x_5555_5555_0000;
+pub const STACK_PAGES: usize = 5; // 20 KiB, like the double fault stack
+pub const MAX_STACKS: usize = 64;
+
+const PAGE_SIZE: usize = 4096;
+const SLOT_SIZE: usize = (STACK_PAGES + 1) * PAGE_SIZE; // + guard page
+pub const STACK_REGION_SIZE: usize = MAX_STACKS * SLOT_SIZE;
+
+/// A stack handed out by the pool. `end` is what goes in `rsp` (or an IST
+/// entry) since stacks grow down.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Stack {
+    slot: usize,
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+}
+
+#[derive(Debug)]
+pub enum StackError {
+    /// All `MAX_STACKS` slots are handed out.
+    PoolExhausted,
+    /// Mapping a new slot failed.
+    MapFailed(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for StackError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        StackError::MapFailed(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackPoolStats {
+    /// Stacks currently handed out.
+    pub in_use: usize,
+    /// Stacks mapped but waiting in the free list.
+    pub free: usize,
+    /// Slots never used yet.
+    pub unused: usize,
+    /// Slots whose mapping failed, never handed out.
+    pub poisoned: usize,
+}
+
+pub struct StackPool {
+    /// Slots below this one have been mapped at least once.
+    next_slot: usize,
+    free: [usize; MAX_STACKS],
+    free_len: usize,
+    poisoned: usize,
+}
+
+impl StackPool {
+    pub const fn new() -> StackPool {
+        StackPool {
+            next_slot: 0,
+            free: [0; MAX_STACKS],
+            free_len: 0,
+            poisoned: 0,
+        }
+    }
+
+    /// Hands out a stack, reusing a freed one if possible and mapping a new slot
+    /// otherwise.
+    pub fn allocate(
+        &mut self,
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    ) -> Result<Stack, StackError> {
+        if self.free_len > 0 {
+            cover!("allocate_recycled");
+            self.free_len -= 1;
+            return Ok(Self::slot_stack(self.free[self.free_len]));
+        }
+        if self.next_slot == MAX_STACKS {
+            cover!("allocate_exhausted");
+            return Err(StackError::PoolExhausted);
+        }
+
+        cover!("allocate_new");
+        let stack = Self::slot_stack(self.next_slot);
+        // a slot that failed halfway keeps what got mapped : the frame
+        // allocator can't take frames back, and mapping the slot again would
+        // fail on the pages already there
+        self.next_slot += 1;
+        if let Err(error) = Self::map_stack(&stack, mapper, frame_allocator) {
+            cover!("allocate_poisoned");
+            self.poisoned += 1;
+            return Err(error);
+        }
+        Ok(stack)
+    }
+
+    fn map_stack(
+        stack: &Stack,
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    ) -> Result<(), StackError> {
+        let page_range = {
+            let start_page = Page::containing_address(stack.start);
+            let end_page = Page::containing_address(stack.end - 1u64);
+            Page::range_inclusive(start_page, end_page)
+        };
+        for page in page_range {
+            let frame = frame_allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)?;
+            let flags =
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+            unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+        }
+        Ok(())
+    }
+
+    /// Gives a stack back to the pool.
+    ///
+    /// Nothing may still be running on it : it will be handed out again as is.
+    pub fn free(&mut self, stack: Stack) {
+        kassert!(
+            !self.free[..self.free_len].contains(&stack.slot),
+            "stack slot {} freed twice",
+            stack.slot
+        );
+        self.free[self.free_len] = stack.slot;
+        self.free_len += 1;
+    }
+
+    pub fn stats(&self) -> StackPoolStats {
+        StackPoolStats {
+            in_use: self.next_slot - self.free_len - self.poisoned,
+            free: self.free_len,
+            unused: MAX_STACKS - self.next_slot,
+            poisoned: self.poisoned,
+        }
+    }
+
+    fn slot_stack(slot: usize) -> Stack {
+        // the guard page is the first page of the slot and stays unmapped
+        let start = VirtAddr::new((STACK_REGION_START + slot * SLOT_SIZE + PAGE_SIZE) as u64);
+        Stack {
+            slot,
+            start,
+            end: start + STACK_PAGES * PAGE_SIZE,
+        }
+    }
+}
+
+pub static STACK_POOL: Mutex<StackPool> = Mutex::new(StackPool::new());
+
+/// Returns the guard page address of the stack containing `addr`, if `addr` is
+/// the guard page of a pool slot. Used to name stack overflows in page faults.
+pub fn guard_page_of(addr: VirtAddr) -> Option<VirtAddr> {
+    let addr = addr.as_u64() as usize;
+    let region_end = STACK_REGION_START + STACK_REGION_SIZE;
+    if addr < STACK_REGION_START || addr >= region_end {
+        return None;
+    }
+    let slot_start = addr - (addr - STACK_REGION_START) % SLOT_SIZE;
+    if addr < slot_start + PAGE_SIZE {
+        Some(VirtAddr::new(slot_start as u64))
+    } else {
+        None
+    }
+}
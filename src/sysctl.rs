@@ -0,0 +1,148 @@
+//! Runtime tunables.
+//!
+//! Subsystems keep their tunable parameters in `AtomicUsize` statics, or
+//! `AtomicIsize` for the ones that can go below zero, and list them in
+//! `TUNABLES` under a dotted name, so they can be read and changed while the
+//! kernel runs instead of recompiling. Values are handled as `isize` here
+//! whatever the static, the ranges keep unsigned ones non negative.
+
+use core::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
+
+use crate::{keyboard, logger, screensaver, time};
+
+enum Value {
+    Unsigned(&'static AtomicUsize),
+    Signed(&'static AtomicIsize),
+}
+
+pub struct Tunable {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub min: isize,
+    pub max: isize,
+    value: Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysctlError {
+    UnknownName,
+    OutOfRange { min: isize, max: isize },
+    /// Not a `name=value` assignment with a decimal value.
+    InvalidAssignment,
+}
+
+impl Tunable {
+    pub fn get(&self) -> isize {
+        match self.value {
+            Value::Unsigned(value) => value.load(Ordering::Relaxed) as isize,
+            Value::Signed(value) => value.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn set(&self, value: isize) -> Result<(), SysctlError> {
+        if value < self.min || value > self.max {
+            return Err(SysctlError::OutOfRange {
+                min: self.min,
+                max: self.max,
+            });
+        }
+        match self.value {
+            // `min` is never negative for these
+            Value::Unsigned(target) => target.store(value as usize, Ordering::Relaxed),
+            Value::Signed(target) => target.store(value, Ordering::Relaxed),
+        }
+        Ok(())
+    }
+}
+
+static TUNABLES: [Tunable; 6] = [
+    Tunable {
+        name: "kernel.log_level",
+        description: "most verbose log level shown, 1 (errors) to 4 (debug)",
+        min: logger::Level::Error as isize,
+        max: logger::Level::Debug as isize,
+        value: Value::Unsigned(&logger::LOG_LEVEL),
+    },
+    Tunable {
+        name: "console.log_level",
+        description: "most verbose log level shown on screen, 1 to 4",
+        min: logger::Level::Error as isize,
+        max: logger::Level::Debug as isize,
+        value: Value::Unsigned(&logger::VGA_LOG_LEVEL),
+    },
+    Tunable {
+        name: "serial.log_level",
+        description: "most verbose log level sent over serial, 1 to 4",
+        min: logger::Level::Error as isize,
+        max: logger::Level::Debug as isize,
+        value: Value::Unsigned(&logger::SERIAL_LOG_LEVEL),
+    },
+    Tunable {
+        name: "keyboard.echo",
+        description: "print decoded keys on the console",
+        min: 0,
+        max: 1,
+        value: Value::Unsigned(&keyboard::ECHO),
+    },
+    Tunable {
+        name: "console.blank_seconds",
+        description: "seconds without input before blanking the console, 0 never blanks",
+        min: 0,
+        max: 24 * 60 * 60,
+        value: Value::Unsigned(&screensaver::BLANK_SECONDS),
+    },
+    Tunable {
+        name: "time.utc_offset",
+        description: "local time offset, minutes east of UTC (-60 is UTC-01:00)",
+        min: -time::MAX_UTC_OFFSET,
+        max: time::MAX_UTC_OFFSET,
+        value: Value::Signed(&time::UTC_OFFSET),
+    },
+];
+
+pub fn all() -> &'static [Tunable] {
+    &TUNABLES
+}
+
+pub fn find(name: &str) -> Option<&'static Tunable> {
+    TUNABLES.iter().find(|tunable| tunable.name == name)
+}
+
+pub fn get(name: &str) -> Result<isize, SysctlError> {
+    find(name).map(Tunable::get).ok_or(SysctlError::UnknownName)
+}
+
+pub fn set(name: &str, value: isize) -> Result<(), SysctlError> {
+    find(name).ok_or(SysctlError::UnknownName)?.set(value)
+}
+
+/// Applies a `name=value` assignment, like `sysctl -w`.
+pub fn apply(assignment: &str) -> Result<(), SysctlError> {
+    let mut parts = assignment.splitn(2, '=');
+    let name = parts.next().unwrap_or("").trim();
+    let value = parts
+        .next()
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or(SysctlError::InvalidAssignment)?;
+    set(name, value)
+}
+
+#[test_case]
+fn test_sysctl_get_set() {
+    let level = get("kernel.log_level").unwrap();
+    assert_eq!(set("kernel.log_level", 0), Err(SysctlError::OutOfRange { min: 1, max: 4 }));
+    assert_eq!(apply("kernel.log_level = 2"), Ok(()));
+    assert_eq!(get("kernel.log_level"), Ok(2));
+    assert_eq!(apply("kernel.log_level"), Err(SysctlError::InvalidAssignment));
+    assert_eq!(get("kernel.nope"), Err(SysctlError::UnknownName));
+    set("kernel.log_level", level).unwrap();
+
+    let offset = get("time.utc_offset").unwrap();
+    assert_eq!(apply("time.utc_offset = -60"), Ok(()));
+    assert_eq!(get("time.utc_offset"), Ok(-60));
+    assert_eq!(
+        set("time.utc_offset", -841),
+        Err(SysctlError::OutOfRange { min: -840, max: 840 })
+    );
+    set("time.utc_offset", offset).unwrap();
+}
@@ -1,3 +1,4 @@
+use core::fmt;
 use core::panic::PanicInfo;
 
 use crate::{debug, done, error, info, serial};
@@ -11,6 +12,39 @@ pub fn panic_handler(info: &PanicInfo) -> ! {
     super::hlt_loop() // not executed but whatever
 }
 
+/// Fixed size `fmt::Write` target, to check formatted output without a heap.
+pub struct FmtBuffer {
+    bytes: [u8; FmtBuffer::CAPACITY],
+    len: usize,
+}
+
+impl FmtBuffer {
+    pub const CAPACITY: usize = 64;
+
+    pub const fn new() -> FmtBuffer {
+        FmtBuffer {
+            bytes: [0; FmtBuffer::CAPACITY],
+            len: 0,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl fmt::Write for FmtBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.bytes
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
 pub trait Testable {
     fn test(&self) -> ();
 }
@@ -32,6 +66,8 @@ pub fn test_runner(tests: &[&dyn Testable]) {
         test.test(); // new
     }
     done!(" --- All was a succes ! --- \n");
+    #[cfg(feature = "coverage")]
+    crate::coverage::dump();
     serial::exit_qemu(serial::QemuExitCode::Success);
 }
 //
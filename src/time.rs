@@ -0,0 +1,365 @@
+//! Tick counting on top of the PIT timer interrupt, cross-checked against the
+//! TSC and the RTC.
+//!
+//! `init` programs the PIT to `HZ`, chosen at build time. Code measuring time
+//! converts with `ms_to_ticks` and `ticks_to_ms` rather than assuming a rate.
+//!
+//! The PIT rate is only nominal : under QEMU it depends on the host speed and
+//! `-icount`, on real hardware on the crystal. The timer handler watches the RTC
+//! seconds and records the tick count and TSC at each change, which gives the
+//! real tick rate and TSC frequency, and `sleep_ms` uses those instead of the
+//! nominal rate once a couple of seconds have been observed.
+//!
+//! The RTC keeps UTC. `local_now` pairs it with the `time.utc_offset` tunable,
+//! and prints as RFC 3339 (`2020-12-23T14:37:42+01:00`).
+
+use core::arch::x86_64::_rdtsc;
+use core::fmt;
+use core::sync::atomic::{AtomicIsize, AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::io::ports::{Pit, PitChannel, PitMode};
+use crate::rtc::{self, DateTime};
+use crate::{debug, info, preempt};
+
+/// Input clock of the PIT, in Hz.
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+
+#[cfg(all(feature = "hz-250", feature = "hz-1000"))]
+compile_error!("the `hz-250` and `hz-1000` features are mutually exclusive");
+
+/// Timer interrupt rate, 100 Hz unless the `hz-250` or `hz-1000` feature is set.
+#[cfg(feature = "hz-1000")]
+pub const HZ: u32 = 1000;
+#[cfg(all(feature = "hz-250", not(feature = "hz-1000")))]
+pub const HZ: u32 = 250;
+#[cfg(not(any(feature = "hz-250", feature = "hz-1000")))]
+pub const HZ: u32 = 100;
+
+/// PIT divisor giving the closest rate to `HZ`.
+pub const PIT_DIVISOR: u32 = (PIT_FREQUENCY + HZ / 2) / HZ;
+/// The rate the divisor really gives, in mHz.
+const NOMINAL_TICK_MILLIHZ: u64 = PIT_FREQUENCY as u64 * 1000 / PIT_DIVISOR as u64;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// TSC when the last timer interrupt was handled.
+static LAST_TICK_TSC: AtomicU64 = AtomicU64::new(0);
+
+static PIT: Mutex<Pit> = Mutex::new(unsafe { Pit::new() });
+
+/// Tick count and TSC at an RTC second change.
+#[derive(Debug, Clone, Copy)]
+struct Edge {
+    ticks: u64,
+    tsc: u64,
+}
+
+struct Calibration {
+    last_second: Option<u8>,
+    /// No RTC reads before this tick, see `RTC_QUIET_TICKS`.
+    next_sample: u64,
+    /// First and latest observed RTC second changes.
+    first: Option<Edge>,
+    last: Option<Edge>,
+    /// RTC seconds between `first` and `last`.
+    seconds: u64,
+}
+
+static CALIBRATION: Mutex<Calibration> = Mutex::new(Calibration {
+    last_second: None,
+    next_sample: 0,
+    first: None,
+    last: None,
+    seconds: 0,
+});
+
+/// Ticks after an RTC second change during which the handler doesn't read the
+/// CMOS : the next change is about `HZ` ticks away, so it only polls on every
+/// tick for the last quarter of the second. The margin covers timer interrupts
+/// lost by a slow host.
+const RTC_QUIET_TICKS: u64 = HZ as u64 * 3 / 4;
+
+/// RTC seconds to observe before trusting the measured rates.
+pub(crate) const MIN_CALIBRATION_SECONDS: u64 = 2;
+
+pub(crate) fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// TSC recorded at the start of the last timer interrupt.
+pub(crate) fn last_tick_tsc() -> u64 {
+    LAST_TICK_TSC.load(Ordering::Relaxed)
+}
+
+fn pit_program(mode: PitMode, count: u16) {
+    interrupts::without_interrupts(|| PIT.lock().program(PitChannel::Timer, mode, count));
+}
+
+/// Makes the PIT raise a single timer interrupt after `count` input clocks.
+/// The periodic tick stops until `pit_periodic` is called.
+pub(crate) fn pit_one_shot(count: u16) {
+    pit_program(PitMode::InterruptOnTerminalCount, count);
+}
+
+/// Puts the PIT in its periodic mode at `HZ`.
+pub(crate) fn pit_periodic() {
+    // a count of 0 means 65536
+    pit_program(PitMode::SquareWave, (PIT_DIVISOR % 65536) as u16);
+}
+
+/// Programs the timer interrupt rate, instead of the 18.2 Hz the BIOS sets.
+pub fn init() {
+    debug!("Timer at {} Hz (PIT divisor {})", HZ, PIT_DIVISOR);
+    pit_periodic();
+}
+
+/// Called by the timer interrupt handler.
+pub(crate) fn tick() {
+    LAST_TICK_TSC.store(rdtsc(), Ordering::Relaxed);
+    let ticks = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+
+    // never spin in the handler : skip this tick if busy or updating
+    let mut calibration = match CALIBRATION.try_lock() {
+        Some(calibration) => calibration,
+        None => return,
+    };
+    if ticks < calibration.next_sample {
+        return;
+    }
+    let second = match rtc::try_seconds() {
+        Some(second) => second,
+        None => return,
+    };
+    if calibration.last_second == Some(second) {
+        return;
+    }
+    let first_sample = calibration.last_second.is_none();
+    calibration.last_second = Some(second);
+    if first_sample {
+        // we don't know when this second started, wait for the next change
+        return;
+    }
+    calibration.next_sample = ticks + RTC_QUIET_TICKS;
+
+    let edge = Edge {
+        ticks,
+        tsc: rdtsc(),
+    };
+    if calibration.first.is_none() {
+        calibration.first = Some(edge);
+    } else {
+        calibration.seconds += 1;
+    }
+    calibration.last = Some(edge);
+}
+
+/// Drops the calibration, for when the tick rate was changed underneath it.
+pub(crate) fn reset_calibration() {
+    interrupts::without_interrupts(|| {
+        let mut calibration = CALIBRATION.lock();
+        calibration.last_second = None;
+        calibration.next_sample = 0;
+        calibration.first = None;
+        calibration.last = None;
+        calibration.seconds = 0;
+    });
+}
+
+/// Number of timer interrupts since interrupts were enabled.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Measured timer and TSC rates against the RTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DriftStats {
+    /// RTC seconds the measurement covers.
+    pub seconds: u64,
+    /// Nominal timer rate, in mHz.
+    pub nominal_tick_millihz: u64,
+    /// Measured timer rate, in mHz. Nominal until calibrated.
+    pub tick_millihz: u64,
+    /// Measured TSC frequency, in Hz, if calibrated.
+    pub tsc_hz: Option<u64>,
+    /// Relative error of the nominal timer rate, in parts per million.
+    pub drift_ppm: i64,
+}
+
+pub fn drift_stats() -> DriftStats {
+    let (seconds, span) = interrupts::without_interrupts(|| {
+        let calibration = CALIBRATION.lock();
+        (
+            calibration.seconds,
+            calibration.first.zip(calibration.last),
+        )
+    });
+
+    let mut stats = DriftStats {
+        seconds,
+        nominal_tick_millihz: NOMINAL_TICK_MILLIHZ,
+        tick_millihz: NOMINAL_TICK_MILLIHZ,
+        tsc_hz: None,
+        drift_ppm: 0,
+    };
+    match span {
+        Some((first, last)) if seconds >= MIN_CALIBRATION_SECONDS => {
+            stats.tick_millihz = (last.ticks - first.ticks) * 1000 / seconds;
+            stats.tsc_hz = Some((last.tsc - first.tsc) / seconds);
+            stats.drift_ppm = (stats.tick_millihz as i64 - NOMINAL_TICK_MILLIHZ as i64)
+                * 1_000_000
+                / NOMINAL_TICK_MILLIHZ as i64;
+        }
+        _ => {}
+    }
+    stats
+}
+
+/// Logs the current drift statistics.
+pub fn report() {
+    let stats = drift_stats();
+    info!(
+        "time: {} RTC seconds observed, timer {}.{:03} Hz (nominal {}.{:03} Hz, {} ppm), TSC {:?} Hz",
+        stats.seconds,
+        stats.tick_millihz / 1000,
+        stats.tick_millihz % 1000,
+        stats.nominal_tick_millihz / 1000,
+        stats.nominal_tick_millihz % 1000,
+        stats.drift_ppm,
+        stats.tsc_hz
+    );
+}
+
+/// Converts milliseconds to timer ticks at `HZ`, rounding up.
+pub const fn ms_to_ticks(ms: u64) -> u64 {
+    (ms * HZ as u64 + 999) / 1000
+}
+
+/// Converts timer ticks to milliseconds at `HZ`, rounding down.
+pub const fn ticks_to_ms(ticks: u64) -> u64 {
+    ticks * 1000 / HZ as u64
+}
+
+/// Waits for at least `ms` milliseconds. Must not be called from atomic context.
+///
+/// Uses the TSC once calibrated, halting while more than a tick remains and
+/// spinning for the rest. Before that it counts ticks, so it has a resolution
+/// of one tick (1000 / `HZ` milliseconds).
+#[track_caller]
+pub fn sleep_ms(ms: u64) {
+    preempt::might_sleep();
+    let stats = drift_stats();
+    match stats.tsc_hz {
+        Some(tsc_hz) => {
+            let deadline = rdtsc() + ms * tsc_hz / 1000;
+            let tick_tsc = tsc_hz * 1000 / stats.tick_millihz;
+            loop {
+                let now = rdtsc();
+                if now >= deadline {
+                    break;
+                }
+                if deadline - now > tick_tsc {
+                    x86_64::instructions::hlt();
+                } else {
+                    core::sync::atomic::spin_loop_hint();
+                }
+            }
+        }
+        None => {
+            // at the measured tick rate, the nominal one until calibrated
+            let deadline = ticks() + (ms * stats.tick_millihz + 999_999) / 1_000_000;
+            while ticks() < deadline {
+                x86_64::instructions::hlt();
+            }
+        }
+    }
+}
+
+/// Largest UTC offset either way, in minutes : offsets go from -14:00 to +14:00.
+pub const MAX_UTC_OFFSET: isize = 14 * 60;
+
+/// Minutes east of UTC (`time.utc_offset`).
+pub static UTC_OFFSET: AtomicIsize = AtomicIsize::new(0);
+
+/// Offset of local time from UTC, in minutes east.
+pub fn utc_offset_minutes() -> i32 {
+    UTC_OFFSET.load(Ordering::Relaxed) as i32
+}
+
+/// A UTC date and time, and the offset to show it at.
+///
+/// Displays as RFC 3339, `Z` for UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub utc: DateTime,
+    pub offset_minutes: i32,
+}
+
+impl LocalTime {
+    /// The wall clock date and time at the offset.
+    pub fn local(&self) -> DateTime {
+        let seconds = self.utc.to_unix() as i64 + self.offset_minutes as i64 * 60;
+        DateTime::from_unix(seconds.max(0) as u64)
+    }
+}
+
+impl fmt::Display for LocalTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let local = self.local();
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            local.year, local.month, local.day, local.hour, local.minute, local.second
+        )?;
+        if self.offset_minutes == 0 {
+            return f.write_str("Z");
+        }
+        let sign = if self.offset_minutes < 0 { '-' } else { '+' };
+        let offset = self.offset_minutes.abs();
+        write!(f, "{}{:02}:{:02}", sign, offset / 60, offset % 60)
+    }
+}
+
+/// Reads the RTC, at the configured UTC offset.
+pub fn local_now() -> LocalTime {
+    LocalTime {
+        utc: rtc::now(),
+        offset_minutes: utc_offset_minutes(),
+    }
+}
+
+#[test_case]
+fn test_tick_conversions_round_trip() {
+    // `HZ` divides 1000, so whole ticks and whole seconds convert exactly
+    assert_eq!(ms_to_ticks(1000), HZ as u64);
+    assert_eq!(ticks_to_ms(ms_to_ticks(1000)), 1000);
+    for &ticks in &[0, 1, 7, HZ as u64] {
+        assert_eq!(ms_to_ticks(ticks_to_ms(ticks)), ticks);
+    }
+    // partial ticks round up
+    assert_eq!(ms_to_ticks(1), 1);
+}
+
+#[test_case]
+fn test_rfc3339_offsets() {
+    fn check(offset_minutes: i32, expected: &str) {
+        use core::fmt::Write;
+
+        let utc = DateTime {
+            year: 2020,
+            month: 12,
+            day: 23,
+            hour: 13,
+            minute: 37,
+            second: 42,
+        };
+        let mut buffer = crate::testing::FmtBuffer::new();
+        write!(buffer, "{}", LocalTime { utc, offset_minutes }).unwrap();
+        assert_eq!(buffer.as_bytes(), expected.as_bytes());
+    }
+
+    check(0, "2020-12-23T13:37:42Z");
+    check(60, "2020-12-23T14:37:42+01:00");
+    check(-330, "2020-12-23T08:07:42-05:30");
+    check(14 * 60, "2020-12-24T03:37:42+14:00");
+}
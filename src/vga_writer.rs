@@ -5,6 +5,9 @@ use core::fmt;
 use lazy_static::lazy_static;
 use spin::Mutex;
 
+use crate::color::{self, Token};
+use crate::io::ports::VgaCrtc;
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -47,6 +50,9 @@ pub struct ScreenChar {
 
 const BUFFER_HEIGHT: usize = 25;
 const BUFFER_WIDTH: usize = 80;
+
+const CRTC_CURSOR_HIGH: u8 = 0x0E;
+const CRTC_CURSOR_LOW: u8 = 0x0F;
 pub const DEFAULT_COLOR_CODE: ColorCode = ColorCode(7);
 
 #[repr(transparent)]
@@ -54,6 +60,19 @@ struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+/// `u64` words in a row of the buffer.
+const ROW_WORDS: usize = BUFFER_WIDTH * core::mem::size_of::<ScreenChar>() / 8;
+/// Four blank cells in the default color, as one word.
+const BLANK_WORD: u64 = 0x0720_0720_0720_0720;
+
+impl Buffer {
+    /// The buffer as `u64` words, to move whole rows at once. The frame buffer
+    /// at 0xb8000 is page aligned, so the words are too.
+    fn words(&mut self) -> *mut u64 {
+        self.chars.as_mut_ptr() as *mut u64
+    }
+}
+
 pub struct Writer {
     column_position: usize,
     pub color_code: ColorCode,
@@ -88,26 +107,43 @@ impl Writer {
         }
     }
 
+    /// Moves every row up by one.
     fn scroll(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
-            }
+        let words = self.buffer.words();
+        // 8 bytes at a time instead of cell by cell, still volatile so the
+        // compiler can't drop or merge the frame buffer writes
+        for word in 0..(BUFFER_HEIGHT - 1) * ROW_WORDS {
+            unsafe {
+                words
+                    .add(word)
+                    .write_volatile(words.add(word + ROW_WORDS).read_volatile())
+            };
         }
         self.clear_row(BUFFER_HEIGHT - 1);
         self.column_position = 0;
     }
 
     fn clear_row(&mut self, row: usize) {
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(ScreenChar {
-                ascii_character: b' ',
-                color_code: DEFAULT_COLOR_CODE,
-            });
+        let words = self.buffer.words();
+        for word in row * ROW_WORDS..(row + 1) * ROW_WORDS {
+            unsafe { words.add(word).write_volatile(BLANK_WORD) };
         }
     }
 
+    /// Moves the hardware cursor after the last character written.
+    pub fn update_cursor(&self) {
+        let column = self.column_position.min(BUFFER_WIDTH - 1);
+        let position = ((BUFFER_HEIGHT - 1) * BUFFER_WIDTH + column) as u16;
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut crtc = CRTC.lock();
+            // the cursor registers don't touch the frame buffer
+            unsafe {
+                crtc.write(CRTC_CURSOR_HIGH, (position >> 8) as u8);
+                crtc.write(CRTC_CURSOR_LOW, position as u8);
+            }
+        });
+    }
+
     pub fn write_string(&mut self, s: &str) {
         if s == "" {
             return;
@@ -122,60 +158,44 @@ impl Writer {
                 }
             }
         } else {
-            let mut should_igniore_next: bool = true;
-            for colorized in s.split("$") {
-                if colorized == "" {
-                    self.write_byte(b'$');
-                    should_igniore_next = true;
-                    continue;
-                }
-                if should_igniore_next {
-                    // in case of $$a0 , empty one will set should_igniore_next, and the next one will print (aa) and will not be interpreted
-                    should_igniore_next = false;
-                    for byte in colorized.chars() {
-                        self.write_byte(byte as u8);
-                    }
-                    continue;
-                }
-                let mut iter = colorized.chars();
-                let ia = iter.next();
-                if ia == Some('!') {
-                    self.color_code = DEFAULT_COLOR_CODE;
-                    for byte in iter {
-                        self.write_byte(byte as u8);
-                    }
-                    continue;
-                }
-                match (ia, iter.next()) {
-                    (Some(a), Some(b)) => {
-                        let mut color = [0; 1];
-                        let color_a = i32::from_str_radix((a).encode_utf8(&mut color), 16);
-                        let color_b = i32::from_str_radix((b).encode_utf8(&mut color), 16);
-                        match (color_a, color_b) {
-                            (Ok(ca), Ok(cb)) => {
-                                self.color_code = ColorCode((ca as u8) << 4 | (cb as u8));
-                                for byte in iter {
-                                    self.write_byte(byte as u8);
-                                }
-                            }
-                            _ => {
-                                for byte in colorized.chars() {
-                                    self.write_byte(byte as u8);
-                                }
-                            }
-                        };
-                    }
-                    _ => {
-                        for byte in colorized.chars() {
-                            self.write_byte(byte as u8);
+            for token in color::tokens(s) {
+                match token {
+                    Token::Text(text) => {
+                        for character in text.chars() {
+                            self.write_byte(to_cp437(character));
                         }
                     }
+                    Token::Color(attribute) => self.color_code = ColorCode(attribute),
+                    Token::Reset => self.color_code = DEFAULT_COLOR_CODE,
                 }
             }
         }
     }
 }
 
+/// Code page 437 from 0x80 on, the character set of the VGA text mode.
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»";
+
+/// The glyph of `character` in code page 437, `?` if it has none.
+fn to_cp437(character: char) -> u8 {
+    if character.is_ascii() {
+        return character as u8;
+    }
+    if let Some(index) = CP437_HIGH.chars().position(|glyph| glyph == character) {
+        return 0x80 + index as u8;
+    }
+    match character {
+        'ß' => 0xE1,
+        'µ' => 0xE6,
+        '±' => 0xF1,
+        '÷' => 0xF6,
+        '°' => 0xF8,
+        '·' => 0xFA,
+        '²' => 0xFD,
+        _ => b'?',
+    }
+}
+
 #[allow(dead_code)]
 pub fn test_print() {
     for a in 0x0..0xFF {
@@ -190,6 +210,9 @@ impl fmt::Write for Writer {
     }
 }
 
+/// The CRT controller, for the cursor. Screen blanking uses it too.
+pub(crate) static CRTC: Mutex<VgaCrtc> = Mutex::new(unsafe { VgaCrtc::new() });
+
 lazy_static! {
     pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
         column_position: 0,
@@ -201,23 +224,30 @@ lazy_static! {
 
 #[macro_export]
 macro_rules! vga_println {
-    () => ($crate::print!("\n"));
-    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+    () => ($crate::vga_print!("\n"));
+    ($fmt:expr $(, $($arg:tt)*)?) => ($crate::vga_print!(concat!($fmt, "\n") $(, $($arg)*)?));
 }
 
 #[macro_export]
 macro_rules! vga_print {
-    ($($arg:tt)*) => ($crate::vga_writer::_print(format_args!($($arg)*)));
+    ($fmt:expr $(, $($arg:tt)*)?) => ({
+        $crate::check_colors!($fmt);
+        $crate::vga_writer::_print(format_args!($fmt $(, $($arg)*)?));
+    });
 }
 
+/// Takes the writer once for the whole message and moves the hardware cursor
+/// at the end only.
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    WRITER.lock().color_code = DEFAULT_COLOR_CODE;
     use x86_64::instructions::interrupts;
 
     interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
+        let mut writer = WRITER.lock();
+        writer.color_code = DEFAULT_COLOR_CODE;
+        writer.write_fmt(args).unwrap();
+        writer.update_cursor();
     });
 }
 
@@ -236,3 +266,44 @@ fn test_println_output() {
         }
     });
 }
+
+#[test_case]
+fn test_scroll_moves_rows_up() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\nfirst\nsecond\n").expect("write failed");
+        let first_char = |row: usize| writer.buffer.chars[row][0].read().ascii_character;
+        assert_eq!(first_char(BUFFER_HEIGHT - 3), b'f');
+        assert_eq!(first_char(BUFFER_HEIGHT - 2), b's');
+        assert_eq!(first_char(BUFFER_HEIGHT - 1), b' ');
+        // the blank word is a space in the default color, in every cell
+        for cell in writer.buffer.chars[BUFFER_HEIGHT - 1].iter() {
+            assert_eq!(
+                cell.read(),
+                ScreenChar {
+                    ascii_character: b' ',
+                    color_code: DEFAULT_COLOR_CODE,
+                }
+            );
+        }
+    });
+}
+
+#[test_case]
+fn test_non_ascii_is_code_page_437() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        write!(writer, "\nêëï€°$$").expect("write failed");
+        let row = &writer.buffer.chars[BUFFER_HEIGHT - 1];
+        let bytes: [u8; 6] = [0x88, 0x89, 0x8B, b'?', 0xF8, b'$'];
+        for (i, &byte) in bytes.iter().enumerate() {
+            assert_eq!(row[i].read().ascii_character, byte);
+        }
+    });
+}
@@ -0,0 +1,283 @@
+//! Page table introspection.
+//!
+//! `for_each_region` walks the active page tables and hands out the mapped
+//! address space as regions : runs of contiguous pages with the same effective
+//! permissions. `audit` checks them for mappings that should not exist, `dump`
+//! prints them all.
+
+use bootloader::bootinfo::MemoryMap;
+use core::fmt;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::{allocator, layout, memory, stack};
+use crate::{done, info, println, warn};
+
+/// Identity maps below this are the bootloader's and the legacy hardware's
+/// (VGA text buffer, BIOS data).
+const LOW_MEMORY_END: u64 = 0x10_0000;
+
+/// Mapped pages with the same permissions, contiguous in virtual memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: VirtAddr,
+    pub size: u64,
+    /// Physical address of the first page.
+    pub phys_start: PhysAddr,
+    /// Effective `WRITABLE`, `USER_ACCESSIBLE` and `NO_EXECUTE` flags.
+    pub flags: PageTableFlags,
+    /// Every page maps to the same physical address.
+    pub identity: bool,
+}
+
+impl Region {
+    pub fn writable(&self) -> bool {
+        self.flags.contains(PageTableFlags::WRITABLE)
+    }
+
+    pub fn executable(&self) -> bool {
+        !self.flags.contains(PageTableFlags::NO_EXECUTE)
+    }
+
+    pub fn user_accessible(&self) -> bool {
+        self.flags.contains(PageTableFlags::USER_ACCESSIBLE)
+    }
+
+    /// What the region maps, from where it starts.
+    pub fn backing(&self, physical_memory_offset: VirtAddr) -> Backing {
+        let start = self.start.as_u64();
+        let within =
+            |base: usize, size: usize| (base as u64..(base + size) as u64).contains(&start);
+        let image = layout::image();
+        if within(allocator::HEAP_START, allocator::HEAP_SIZE) {
+            Backing::Heap
+        } else if within(stack::STACK_REGION_START, stack::STACK_REGION_SIZE) {
+            Backing::Stacks
+        } else if (image.start..image.end).contains(&self.start) {
+            Backing::Kernel
+        } else if start.wrapping_sub(self.phys_start.as_u64()) == physical_memory_offset.as_u64() {
+            Backing::PhysicalMemory
+        } else if self.identity {
+            Backing::Identity
+        } else {
+            Backing::Bootloader
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    /// The kernel image.
+    Kernel,
+    Heap,
+    /// The stack pool.
+    Stacks,
+    /// The mapping of all physical memory at the offset.
+    PhysicalMemory,
+    /// Identity mapped, low memory and devices.
+    Identity,
+    /// Anything else, left by the bootloader (its stack, the boot info).
+    Bootloader,
+}
+
+impl Backing {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backing::Kernel => "kernel",
+            Backing::Heap => "heap",
+            Backing::Stacks => "stacks",
+            Backing::PhysicalMemory => "physical memory",
+            Backing::Identity => "identity",
+            Backing::Bootloader => "bootloader",
+        }
+    }
+}
+
+/// Makes an address canonical by sign extending bit 47.
+fn canonical(addr: u64) -> u64 {
+    if addr & (1 << 47) != 0 {
+        addr | 0xFFFF_0000_0000_0000
+    } else {
+        addr
+    }
+}
+
+/// Calls `f` with the virtual address, physical address, size and effective
+/// flags of every leaf entry below the table at `table_addr`.
+unsafe fn walk_table(
+    table_addr: PhysAddr,
+    level: u32,
+    base: u64,
+    flags: PageTableFlags,
+    physical_memory_offset: VirtAddr,
+    f: &mut dyn FnMut(u64, PhysAddr, u64, PageTableFlags),
+) {
+    let table_ptr: *const PageTable = (physical_memory_offset + table_addr.as_u64()).as_ptr();
+    let table = &*table_ptr;
+    let entry_size = 1u64 << (12 + 9 * (level - 1));
+
+    for (index, entry) in table.iter().enumerate() {
+        let entry_flags = entry.flags();
+        if !entry_flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let virt = canonical(base + index as u64 * entry_size);
+        let effective = memory::combine_flags(flags, entry_flags);
+        // the level 4 table can't hold huge pages, the level 1 bit means PAT
+        if level == 1 || (level != 4 && entry_flags.contains(PageTableFlags::HUGE_PAGE)) {
+            f(virt, entry.addr(), entry_size, effective);
+        } else {
+            walk_table(entry.addr(), level - 1, virt, effective, physical_memory_offset, f);
+        }
+    }
+}
+
+/// Calls `f` with every mapped region of the active address space, in
+/// increasing address order.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `physical_memory_offset`.
+pub unsafe fn for_each_region(physical_memory_offset: VirtAddr, mut f: impl FnMut(&Region)) {
+    let (level_4_table_frame, _) = Cr3::read();
+    let relevant =
+        PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+    let mut current: Option<Region> = None;
+
+    walk_table(
+        level_4_table_frame.start_address(),
+        4,
+        0,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
+        physical_memory_offset,
+        &mut |virt, phys, size, flags| {
+            let flags = flags & relevant;
+            let identity = virt == phys.as_u64();
+            if let Some(region) = current.as_mut() {
+                if region.start.as_u64() + region.size == virt
+                    && region.flags == flags
+                    && region.identity == identity
+                {
+                    region.size += size;
+                    return;
+                }
+                f(region);
+            }
+            current = Some(Region {
+                start: VirtAddr::new(virt),
+                size,
+                phys_start: phys,
+                flags,
+                identity,
+            });
+        },
+    );
+    if let Some(region) = current {
+        f(&region);
+    }
+}
+
+/// Sets `NO_EXECUTE` on the level 4 entries of the bootloader's physical
+/// memory mapping, which it maps writable and executable.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped at `physical_memory_offset` and that
+/// this mapping has its level 4 entries to itself, as the bootloader does.
+pub unsafe fn protect_physical_memory(physical_memory_offset: VirtAddr, memory_map: &MemoryMap) {
+    let physical_end = memory_map
+        .iter()
+        .map(|region| region.range.end_addr())
+        .max()
+        .unwrap_or(0);
+    let first = u64::from(physical_memory_offset.p4_index());
+    let last = u64::from((physical_memory_offset + physical_end.max(1) - 1u64).p4_index());
+
+    let level_4_table = memory::active_level_4_table(physical_memory_offset);
+    for index in first..=last {
+        let entry = &mut level_4_table[index as usize];
+        if !entry.is_unused() {
+            entry.set_flags(entry.flags() | PageTableFlags::NO_EXECUTE);
+        }
+    }
+    x86_64::instructions::tlb::flush_all();
+}
+
+/// Findings of `audit`, in regions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AuditReport {
+    pub regions: usize,
+    pub writable_executable: usize,
+    /// There is no user space yet : any user accessible page is a leak.
+    pub user_accessible: usize,
+    /// Identity maps above low memory.
+    pub identity: usize,
+}
+
+/// Walks the active page tables and logs every region that is writable and
+/// executable, user accessible, or identity mapped outside low memory.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `physical_memory_offset`.
+pub unsafe fn audit(physical_memory_offset: VirtAddr) -> AuditReport {
+    let mut report = AuditReport::default();
+    for_each_region(physical_memory_offset, |region| {
+        report.regions += 1;
+        let range = (region.start.as_u64(), region.start.as_u64() + (region.size - 1));
+        if region.writable() && region.executable() {
+            report.writable_executable += 1;
+            warn!("vmaudit: $0C{:#x}..={:#x}$! is writable and executable", range.0, range.1);
+        }
+        if region.user_accessible() {
+            report.user_accessible += 1;
+            warn!("vmaudit: $0C{:#x}..={:#x}$! is user accessible", range.0, range.1);
+        }
+        if region.identity && range.1 >= LOW_MEMORY_END {
+            report.identity += 1;
+            warn!("vmaudit: $0C{:#x}..={:#x}$! is identity mapped", range.0, range.1);
+        }
+    });
+
+    if report.writable_executable + report.user_accessible + report.identity == 0 {
+        done!("vmaudit: {} regions, nothing to report", report.regions);
+    } else {
+        info!("vmaudit: {} regions, {:?}", report.regions, report);
+    }
+    report
+}
+
+/// A size in bytes, printed in the largest unit it is a whole number of.
+struct Size(u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let units: [(&str, u64); 3] = [("GiB", 1 << 30), ("MiB", 1 << 20), ("KiB", 1 << 10)];
+        match units.iter().find(|(_, unit)| self.0 % unit == 0) {
+            Some((name, unit)) => write!(f, "{} {}", self.0 / unit, name),
+            None => write!(f, "{} B", self.0),
+        }
+    }
+}
+
+/// Prints every region of the active address space : range, permissions,
+/// backing and size.
+///
+/// This function is unsafe because the caller must guarantee that the
+/// complete physical memory is mapped to virtual memory at the passed
+/// `physical_memory_offset`.
+pub unsafe fn dump(physical_memory_offset: VirtAddr) {
+    println!("vmmap:");
+    for_each_region(physical_memory_offset, |region| {
+        println!(
+            "  {:#018x}..{:#018x} r{}{}{} {:<15} {}",
+            region.start.as_u64(),
+            region.start.as_u64() + region.size,
+            if region.writable() { 'w' } else { '-' },
+            if region.executable() { 'x' } else { '-' },
+            if region.user_accessible() { 'u' } else { '-' },
+            region.backing(physical_memory_offset).name(),
+            Size(region.size)
+        );
+    });
+}
@@ -75,3 +75,34 @@ fn many_boxes() {
         assert_eq!(*x, i);
     }
 }
+
+use genos::memory::{stats, HeapStats};
+
+fn blocks_of_256(stats: &HeapStats) -> usize {
+    // class 4 holds the blocks of 256 to 511 bytes
+    stats.histogram[4]
+}
+
+#[test_case]
+fn fragmentation_stats() {
+    let before = stats();
+    assert_eq!(stats(), before);
+    assert_eq!(before.histogram.iter().sum::<usize>(), before.free_blocks);
+    assert!(before.largest_free_block <= before.free);
+
+    let mut boxes: [Option<Box<[u8; 256]>>; 8] = Default::default();
+    for slot in boxes.iter_mut() {
+        *slot = Some(Box::new([0; 256]));
+    }
+    // free every other one, each between two live boxes
+    for slot in boxes.iter_mut().skip(1).step_by(2).take(3) {
+        *slot = None;
+    }
+    let fragmented = stats();
+    assert_eq!(fragmented.used, before.used + 5 * 256);
+    assert!(blocks_of_256(&fragmented) >= blocks_of_256(&before) + 3);
+    assert!(fragmented.largest_free_block < fragmented.free);
+
+    drop(boxes);
+    assert_eq!(stats(), before);
+}
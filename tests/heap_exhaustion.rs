@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::alloc::Layout;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use genos::allocator;
+use genos::serial::{exit_qemu, QemuExitCode};
+use genos::{done, error, info};
+
+/// Set once the heap is up, before the allocation that must fail.
+static EXHAUSTING: AtomicBool = AtomicBool::new(false);
+
+/// Size of the allocation that must fail, the smallest block the heap hands out.
+const SMALL: usize = 16;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use genos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    info!("heap_exhaustion::alloc_error_panics...\t");
+
+    genos::stage1();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    // take 1 KiB blocks until none fits, then small ones to use up the rest
+    let mut taken = 0;
+    for &size in &[1024, SMALL] {
+        let layout = Layout::from_size_align(size, 1).unwrap();
+        while !unsafe { alloc::alloc::alloc(layout) }.is_null() {
+            taken += size;
+        }
+    }
+    let left = allocator::stats().largest_free_block;
+    assert!(left < SMALL, "{} bytes still free after taking {}", left, taken);
+
+    // so it's an allocation this small that has to fail
+    EXHAUSTING.store(true, Ordering::SeqCst);
+    let small = Box::new([0u8; SMALL]);
+
+    EXHAUSTING.store(false, Ordering::SeqCst);
+    panic!("allocated {:p} from an exhausted heap", small);
+}
+
+/// The allocation error handler panics : that is the expected outcome, any
+/// other panic fails the test.
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    if EXHAUSTING.load(Ordering::SeqCst) {
+        done!("heap_exhaustion::alloc_error_panics");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        error!("[failed]");
+        error!("{}", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    genos::hlt_loop()
+}
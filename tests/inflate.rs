@@ -0,0 +1,113 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(genos::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use genos::allocator;
+    use genos::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+
+    genos::stage1();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    genos::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    genos::testing::panic_handler(info)
+}
+
+use alloc::vec::Vec;
+use genos::inflate::{gunzip, inflate, is_gzip, InflateError};
+
+// `printf 'hello hello hello world'` through zlib with Z_FIXED, raw deflate
+const FIXED: [u8; 15] = [
+    0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x22, 0xcb, 0xf3, 0x8b, 0x72, 0x52, 0x00,
+];
+
+// `printf 'stored'` through zlib at level 0, raw deflate
+const STORED: [u8; 11] = [0x01, 0x06, 0x00, 0xf9, 0xff, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x64];
+
+// `expected_payload()` gzipped at level 9 with the file name `payload.txt`.
+// Skewed letters without long repeats, so zlib picks a dynamic block.
+const GZIPPED: [u8; 289] = [
+    0x1f, 0x8b, 0x08, 0x08, 0x00, 0x00, 0x00, 0x00, 0x02, 0xff, 0x70, 0x61, 0x79, 0x6c, 0x6f, 0x61,
+    0x64, 0x2e, 0x74, 0x78, 0x74, 0x00, 0x25, 0x51, 0x41, 0x0a, 0xc4, 0x40, 0x08, 0xbb, 0xfb, 0x0a,
+    0xbf, 0x26, 0x4c, 0xc0, 0xb9, 0x38, 0xa0, 0xf9, 0x3f, 0x1b, 0xbb, 0x2d, 0xb4, 0x1d, 0x35, 0x31,
+    0x49, 0x89, 0x7a, 0x0e, 0x16, 0xc7, 0x70, 0x6e, 0xba, 0x03, 0x81, 0x79, 0x55, 0x80, 0x0d, 0x0e,
+    0x70, 0x81, 0x41, 0xdd, 0x43, 0xa2, 0xc2, 0x8b, 0xad, 0xb3, 0x13, 0x20, 0x5c, 0x37, 0x2f, 0xf1,
+    0xd8, 0x73, 0x76, 0x94, 0xf1, 0x68, 0x8b, 0x57, 0x03, 0x85, 0xa7, 0x99, 0x07, 0xf1, 0xd0, 0xe3,
+    0x75, 0x0a, 0xa3, 0xab, 0x86, 0xd4, 0xa8, 0x5a, 0xd4, 0x0a, 0x15, 0x0d, 0x1d, 0x42, 0xa2, 0x3d,
+    0xf4, 0x44, 0x04, 0x2c, 0xcc, 0xdc, 0x3e, 0x78, 0xb9, 0x55, 0xce, 0x57, 0x9e, 0xe8, 0x25, 0x0d,
+    0x94, 0x4d, 0x5f, 0x66, 0xf2, 0x7e, 0x34, 0xdb, 0x23, 0x99, 0x64, 0x68, 0x7e, 0x85, 0x35, 0xfa,
+    0x36, 0x3e, 0x57, 0x38, 0x94, 0x01, 0x3a, 0x9b, 0x7d, 0xc5, 0xf0, 0xde, 0xf3, 0x1b, 0x83, 0x14,
+    0x96, 0xac, 0x57, 0x92, 0xf2, 0x26, 0x32, 0x43, 0xf6, 0xc5, 0xbe, 0xda, 0xe3, 0xb3, 0xcc, 0xc4,
+    0x67, 0xd2, 0x42, 0x72, 0x53, 0x7e, 0xec, 0xc4, 0x9a, 0xeb, 0xe9, 0x3d, 0x44, 0x72, 0xd4, 0x0d,
+    0x1c, 0xa9, 0x42, 0x1e, 0xa6, 0xed, 0x2e, 0x17, 0x87, 0x60, 0xd3, 0xaa, 0xfc, 0xe9, 0xa4, 0x0c,
+    0xbb, 0xaf, 0x71, 0xf5, 0x39, 0xef, 0x05, 0x32, 0xdf, 0xcc, 0xa6, 0xab, 0x89, 0x1e, 0xff, 0xf6,
+    0x68, 0x91, 0x62, 0x90, 0x96, 0x31, 0x23, 0x52, 0xb1, 0x29, 0x84, 0xe1, 0x79, 0xca, 0xa4, 0x7c,
+    0x85, 0x94, 0x90, 0x7b, 0x59, 0x86, 0x32, 0x8a, 0x45, 0x95, 0x52, 0x95, 0x77, 0x89, 0xc0, 0x36,
+    0x2b, 0x8f, 0xba, 0x1b, 0xf9, 0x4e, 0x52, 0x6f, 0xbf, 0x83, 0x56, 0x9d, 0x37, 0xf5, 0x23, 0xff,
+    0x78, 0x61, 0xbd, 0xae, 0x31, 0x77, 0x1f, 0xe2, 0x07, 0xae, 0x4d, 0x97, 0x41, 0x00, 0x02, 0x00,
+    0x00,
+];
+
+/// Gzip header, then the file name and its terminator.
+const GZIPPED_DEFLATE_START: usize = 10 + 12;
+
+fn expected_payload() -> Vec<u8> {
+    const LETTERS: &[u8] = b"eeeeeeeetttaaoinshr d\n";
+    let mut seed: u32 = 1;
+    let mut payload = Vec::new();
+    for _ in 0..512 {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        payload.push(LETTERS[(seed >> 16) as usize % LETTERS.len()]);
+    }
+    payload
+}
+
+#[test_case]
+fn inflate_fixed_block() {
+    assert_eq!(&inflate(&FIXED).unwrap()[..], &b"hello hello hello world"[..]);
+}
+
+#[test_case]
+fn inflate_stored_block() {
+    assert_eq!(&inflate(&STORED).unwrap()[..], &b"stored"[..]);
+}
+
+#[test_case]
+fn gunzip_dynamic_block() {
+    assert!(is_gzip(&GZIPPED));
+    // BFINAL set, BTYPE 2
+    assert_eq!(GZIPPED[GZIPPED_DEFLATE_START] & 0b111, 0b101);
+    assert_eq!(gunzip(&GZIPPED).unwrap(), expected_payload());
+}
+
+#[test_case]
+fn gunzip_detects_corruption() {
+    let mut corrupted = GZIPPED;
+    let crc_offset = corrupted.len() - 8;
+    corrupted[crc_offset] ^= 0xFF;
+    assert_eq!(gunzip(&corrupted), Err(InflateError::ChecksumMismatch));
+
+    assert_eq!(
+        gunzip(&GZIPPED[..GZIPPED.len() / 2]),
+        Err(InflateError::UnexpectedEof)
+    );
+    assert_eq!(gunzip(&FIXED), Err(InflateError::InvalidHeader));
+}
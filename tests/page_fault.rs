@@ -0,0 +1,98 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use genos::memory::{self, BootInfoFrameAllocator};
+use genos::serial::{exit_qemu, QemuExitCode};
+use genos::{done, error, info};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+/// Far from the heap and the stack pool, unmapped at boot.
+const DEMAND_PAGE: u64 = 0x_6666_6666_0000;
+
+static MAPPER: Mutex<Option<(OffsetPageTable<'static>, BootInfoFrameAllocator)>> =
+    Mutex::new(None);
+static FAULTS: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+/// Maps the faulting page and returns, so the faulting instruction is retried.
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: &mut InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    use x86_64::registers::control::Cr2;
+
+    FAULTS.fetch_add(1, Ordering::Relaxed);
+    let page = Page::containing_address(Cr2::read());
+    if page.start_address().as_u64() != DEMAND_PAGE
+        || error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+    {
+        fail("unexpected page fault");
+    }
+
+    // main holds no lock while touching the demand page
+    let mut guard = MAPPER.try_lock().unwrap_or_else(|| fail("mapper busy"));
+    let (mapper, frame_allocator) = guard.as_mut().unwrap_or_else(|| fail("no mapper"));
+    let frame = frame_allocator
+        .allocate_frame()
+        .unwrap_or_else(|| fail("out of frames"));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+        Ok(flush) => flush.flush(),
+        Err(_) => fail("mapping the demand page failed"),
+    }
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    info!("page_fault::demand_mapping...\t");
+
+    // no PIC and no interrupts : the page fault is the only expected entry
+    genos::gdt::init();
+    TEST_IDT.load();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    *MAPPER.lock() = Some((mapper, frame_allocator));
+
+    let ptr = DEMAND_PAGE as *mut u64;
+    unsafe {
+        ptr.write_volatile(0xdead_beef);
+        assert_eq!(ptr.read_volatile(), 0xdead_beef);
+        ptr.add(1).write_volatile(42);
+    }
+    assert_eq!(FAULTS.load(Ordering::Relaxed), 1);
+
+    done!("page_fault::demand_mapping");
+    exit_qemu(QemuExitCode::Success);
+    genos::hlt_loop()
+}
+
+fn fail(reason: &str) -> ! {
+    error!("[failed] {}", reason);
+    exit_qemu(QemuExitCode::Failed);
+    genos::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    error!("[failed]");
+    error!("{}", info);
+    exit_qemu(QemuExitCode::Failed);
+    genos::hlt_loop()
+}
@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use core::panic::PanicInfo;
+use genos::serial::{exit_qemu, QemuExitCode};
+use genos::{done, error, info};
+use lazy_static::lazy_static;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(genos::gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame: &mut InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    done!("stack_overflow::stack_overflow");
+    exit_qemu(QemuExitCode::Success);
+    genos::hlt_loop()
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    info!("stack_overflow::stack_overflow...\t");
+
+    // only the GDT (for the IST stack) and our own IDT : a page fault on the
+    // guard page has no handler and escalates to a double fault
+    genos::gdt::init();
+    TEST_IDT.load();
+
+    stack_overflow();
+
+    panic!("Execution continued after stack overflow");
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+    // prevent tail recursion optimizations
+    volatile::Volatile::new(0).read();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    error!("[failed]");
+    error!("{}", info);
+    exit_qemu(QemuExitCode::Failed);
+    genos::hlt_loop()
+}
@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(genos::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use genos::memory::BootInfoFrameAllocator;
+use genos::stack::{STACK_PAGES, STACK_POOL};
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, OffsetPageTable, PhysFrame, Size4KiB};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+static MAPPER: Mutex<Option<(OffsetPageTable<'static>, BootInfoFrameAllocator)>> =
+    Mutex::new(None);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use genos::memory;
+
+    genos::stage1();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    *MAPPER.lock() = Some((mapper, frame_allocator));
+
+    test_main();
+    genos::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    genos::testing::panic_handler(info)
+}
+
+#[test_case]
+fn stacks_are_usable_and_recycled() {
+    let mut guard = MAPPER.lock();
+    let (mapper, frame_allocator) = guard.as_mut().unwrap();
+    let mut pool = STACK_POOL.lock();
+    let before = pool.stats();
+
+    let first = pool.allocate(mapper, frame_allocator).unwrap();
+    let second = pool.allocate(mapper, frame_allocator).unwrap();
+    assert_eq!(first.end - first.start, (STACK_PAGES * 4096) as u64);
+    // either may come from the free list, so in any order
+    assert!(
+        second.start > first.end || first.start > second.end,
+        "stacks must not be adjacent"
+    );
+
+    // the whole stack is mapped and writable
+    for offset in (0..first.end - first.start).step_by(4096) {
+        let ptr = (first.start + offset).as_mut_ptr::<u64>();
+        unsafe { ptr.write_volatile(offset) };
+        assert_eq!(unsafe { ptr.read_volatile() }, offset);
+    }
+
+    assert_eq!(pool.stats().in_use, before.in_use + 2);
+    let first_start = first.start;
+    pool.free(first);
+    assert_eq!(pool.stats().free, before.free + 1);
+    let recycled = pool.allocate(mapper, frame_allocator).unwrap();
+    assert_eq!(recycled.start, first_start);
+    assert_eq!(pool.stats().in_use, before.in_use + 2);
+    assert_eq!(pool.stats().free, before.free);
+
+    pool.free(recycled);
+    pool.free(second);
+}
+
+#[test_case]
+fn guard_pages_are_detected() {
+    use genos::stack::guard_page_of;
+
+    let mut guard = MAPPER.lock();
+    let (mapper, frame_allocator) = guard.as_mut().unwrap();
+    let mut pool = STACK_POOL.lock();
+    let stack = pool.allocate(mapper, frame_allocator).unwrap();
+
+    assert_eq!(guard_page_of(stack.start - 8u64), Some(stack.start - 4096u64));
+    assert_eq!(guard_page_of(stack.start), None);
+    assert_eq!(guard_page_of(stack.end - 8u64), None);
+    pool.free(stack);
+}
+
+/// Hands out `left` frames, then fails.
+struct Limited<'a> {
+    inner: &'a mut BootInfoFrameAllocator,
+    left: usize,
+}
+
+unsafe impl FrameAllocator<Size4KiB> for Limited<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+        self.inner.allocate_frame()
+    }
+}
+
+#[test_case]
+fn failed_mapping_does_not_block_the_pool() {
+    let mut guard = MAPPER.lock();
+    let (mapper, frame_allocator) = guard.as_mut().unwrap();
+    let mut pool = STACK_POOL.lock();
+    let before = pool.stats();
+
+    let mut limited = Limited {
+        inner: &mut *frame_allocator,
+        left: STACK_PAGES / 2,
+    };
+    assert!(pool.allocate(mapper, &mut limited).is_err());
+    assert_eq!(pool.stats().poisoned, before.poisoned + 1);
+    assert_eq!(pool.stats().in_use, before.in_use);
+
+    let stack = pool.allocate(mapper, frame_allocator).unwrap();
+    assert_eq!(pool.stats().in_use, before.in_use + 1);
+    pool.free(stack);
+}
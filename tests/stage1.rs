@@ -19,7 +19,7 @@ fn boot_empty() {}
 #[test_case]
 fn print_colored() {
     print!("<");
-    print!("$0agreen $$ $ok $!reset $o");
+    print!("$0agreen $$ $!reset $0C$$ $!");
     print!(">");
 }
 
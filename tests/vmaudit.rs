@@ -0,0 +1,103 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(genos::testing::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use genos::vm;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+static PHYS_MEM_OFFSET: AtomicU64 = AtomicU64::new(0);
+/// End of physical memory, from the memory map.
+static PHYS_MEM_END: AtomicU64 = AtomicU64::new(0);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use genos::allocator;
+    use genos::memory::{self, BootInfoFrameAllocator};
+
+    genos::stage1();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    unsafe { vm::protect_physical_memory(phys_mem_offset, &boot_info.memory_map) };
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    PHYS_MEM_OFFSET.store(phys_mem_offset.as_u64(), Ordering::Relaxed);
+    let phys_mem_end = boot_info
+        .memory_map
+        .iter()
+        .map(|region| region.range.end_addr())
+        .max()
+        .unwrap_or(0);
+    PHYS_MEM_END.store(phys_mem_end, Ordering::Relaxed);
+
+    test_main();
+    genos::hlt_loop()
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    genos::testing::panic_handler(info)
+}
+
+fn offset() -> VirtAddr {
+    VirtAddr::new(PHYS_MEM_OFFSET.load(Ordering::Relaxed))
+}
+
+#[test_case]
+fn no_user_accessible_pages() {
+    let report = unsafe { vm::audit(offset()) };
+    assert!(report.regions > 0);
+    assert_eq!(report.user_accessible, 0);
+}
+
+// whatever the bootloader leaves in low memory, what the kernel maps is W^X :
+// its image, the heap, the stacks and the physical memory window
+#[test_case]
+fn kernel_mappings_are_not_writable_and_executable() {
+    use vm::Backing;
+
+    let physical_memory =
+        offset().as_u64()..offset().as_u64() + PHYS_MEM_END.load(Ordering::Relaxed);
+    let mut kernel = false;
+    unsafe {
+        vm::for_each_region(offset(), |region| {
+            let checked = match region.backing(offset()) {
+                Backing::Kernel => {
+                    kernel = true;
+                    true
+                }
+                Backing::Heap | Backing::Stacks => true,
+                _ => physical_memory.contains(&region.start.as_u64()),
+            };
+            if checked {
+                assert!(!(region.writable() && region.executable()));
+            }
+        });
+    }
+    assert!(kernel);
+}
+
+#[test_case]
+fn regions_are_attributed() {
+    use vm::Backing;
+
+    let (mut kernel, mut heap, mut physical_memory) = (false, false, false);
+    unsafe {
+        vm::for_each_region(offset(), |region| match region.backing(offset()) {
+            Backing::Kernel => kernel = true,
+            Backing::Heap => {
+                heap = true;
+                assert!(!region.executable());
+            }
+            Backing::PhysicalMemory => physical_memory = true,
+            _ => {}
+        });
+        vm::dump(offset());
+    }
+    assert!(kernel && heap && physical_memory);
+}